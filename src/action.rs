@@ -0,0 +1,275 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::announce::{Announcement, Announcer};
+use crate::chatbot::{Bot, Bots, ChatBot, Toggle};
+use crate::error::{self, Error};
+use crate::metrics;
+use crate::program::{Program, Running};
+use crate::streamelements::{CommandRef, Reply, Rewrite};
+use crate::webhook::Webhook;
+use crate::{format_duration, write_output, Binding, Config, RedemptionEvent};
+
+/// What an action gets to work with when a window opens or closes.
+pub struct Context<'a> {
+    pub config: &'a Config,
+    pub binding: &'a Binding,
+    /// The redemption that opened the window.
+    pub event: &'a RedemptionEvent,
+    /// How long the window stays open from now on.
+    pub remaining: Duration,
+    pub bots: &'a Bots,
+    pub output: &'a Arc<Mutex<String>>,
+}
+
+impl Context<'_> {
+    /// The chat bot of the binding.
+    pub fn bot(&self) -> Result<&dyn ChatBot> {
+        self.bots.get(self.binding.bot)
+    }
+
+    pub fn log(&self, text: &str) {
+        write_output(self.output, text);
+    }
+
+    /// Fills in the template from the redemption, see [`render`].
+    pub fn render(&self, template: &str) -> String {
+        render(template, self.event, self.remaining)
+    }
+}
+
+/// Something a binding does while its window is open.
+///
+/// `start` runs when the window opens and `stop` when it closes, on the same
+/// value, so an action can keep whatever it needs to undo itself.
+#[async_trait]
+pub trait Action: Send + Sync {
+    /// Short description for the log.
+    fn name(&self) -> String;
+    async fn start(&mut self, context: &Context<'_>) -> Result<()>;
+    async fn stop(&mut self, context: &Context<'_>) -> Result<()>;
+
+    /// Runs once every action of the window has started, for what should
+    /// only happen if the window really opens.
+    async fn opened(&mut self, _context: &Context<'_>) {}
+
+    /// How long before the window closes `remind` should run, if at all.
+    fn reminder(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Runs once when the window is about to close, again if it got extended since.
+    async fn remind(&mut self, _context: &Context<'_>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An action as written in the config, one variant per kind.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ActionConfig {
+    /// Enables a command of the binding's chat bot while the window is open.
+    #[serde(rename = "toggle_command", alias = "streamelements_toggle")]
+    ToggleCommand(CommandRef),
+    /// Changes a StreamElements command's reply while the window is open.
+    #[serde(rename = "streamelements_reply")]
+    StreamElementsReply(Reply),
+    /// Sends chat messages when the window opens, is about to close and closes.
+    Announce(Announcement),
+    /// Sends the redemption to a URL when the window opens.
+    Webhook(Webhook),
+    /// Runs a local program when the window opens and optionally another one
+    /// when it closes.
+    RunProgram(Program),
+}
+
+impl Default for ActionConfig {
+    fn default() -> ActionConfig {
+        ActionConfig::ToggleCommand(CommandRef::named("fishinge"))
+    }
+}
+
+impl ActionConfig {
+    pub fn build(&self) -> Box<dyn Action> {
+        match self {
+            ActionConfig::ToggleCommand(command) => Box::new(Toggle::new(command.clone())),
+            ActionConfig::StreamElementsReply(reply) => Box::new(Rewrite::new(reply.clone())),
+            ActionConfig::Announce(announcement) => Box::new(Announcer::new(announcement.clone())),
+            ActionConfig::Webhook(webhook) => Box::new(webhook.clone()),
+            ActionConfig::RunProgram(program) => Box::new(Running::new(program.clone())),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ActionConfig::ToggleCommand(command) => format!("!{}", command.command_name),
+            ActionConfig::StreamElementsReply(reply) => {
+                format!("reply of !{}", reply.command.command_name)
+            }
+            ActionConfig::Announce(_) => "announcements".to_owned(),
+            ActionConfig::Webhook(_) => "webhook".to_owned(),
+            ActionConfig::RunProgram(program) => format!("run {}", program.start.program),
+        }
+    }
+
+    /// Checks what can be checked without running the action.
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        match self {
+            ActionConfig::Announce(announcement) => announcement.validate(),
+            ActionConfig::Webhook(webhook) => webhook.validate(&config.webhook_secrets),
+            ActionConfig::RunProgram(program) => program.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    /// The bot this action works on for a binding using `bot`, if any.
+    pub fn bot(&self, bot: Bot) -> Option<Bot> {
+        match self {
+            ActionConfig::ToggleCommand(_) => Some(bot),
+            ActionConfig::StreamElementsReply(_) => Some(Bot::StreamElements),
+            ActionConfig::Announce(_) | ActionConfig::Webhook(_) | ActionConfig::RunProgram(_) => {
+                None
+            }
+        }
+    }
+
+    /// The chat bot command this action works on, if any.
+    pub fn command(&self) -> Option<&CommandRef> {
+        match self {
+            ActionConfig::ToggleCommand(command) => Some(command),
+            ActionConfig::StreamElementsReply(reply) => Some(&reply.command),
+            ActionConfig::Announce(_) | ActionConfig::Webhook(_) | ActionConfig::RunProgram(_) => {
+                None
+            }
+        }
+    }
+}
+
+/// Fills in `{user}`, `{login}`, `{input}`, `{reward}` and `{cost}` from the
+/// redemption and `{remaining}` with how long the window stays open.
+///
+/// Unknown placeholders are left as they are, and the filled in values are
+/// not looked at again, so viewers cannot inject placeholders via their input.
+pub fn render(template: &str, event: &RedemptionEvent, remaining: Duration) -> String {
+    render_with(template, event, remaining, str::to_owned)
+}
+
+/// Like [`render`], but passes the filled in values through `escape` first.
+pub fn render_with(
+    template: &str,
+    event: &RedemptionEvent,
+    remaining: Duration,
+    escape: impl Fn(&str) -> String,
+) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        match &rest[1..end] {
+            "user" => rendered.push_str(&escape(&event.user_name)),
+            "login" => rendered.push_str(&escape(&event.user_login)),
+            "input" => rendered.push_str(&escape(&event.user_input)),
+            "reward" => rendered.push_str(&escape(&event.reward.title)),
+            "cost" => rendered.push_str(&escape(&event.reward.cost.to_string())),
+            "remaining" => rendered.push_str(&escape(&format_duration(remaining))),
+            _ => {
+                rendered.push('{');
+                rest = &rest[1..];
+                continue;
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Starts the actions in order. If one fails, those already started are
+/// stopped again and the error is returned. Otherwise they all hear
+/// that the window opened.
+pub async fn start_all(
+    actions: &mut [Box<dyn Action>],
+    context: &Context<'_>,
+) -> std::result::Result<(), Error> {
+    for index in 0..actions.len() {
+        let result = actions[index].start(context).await;
+        metrics::action(
+            &context.config.profile,
+            &actions[index].name(),
+            "start",
+            result.is_ok(),
+        );
+        if let Err(err) = result {
+            stop_all(&mut actions[..index], context).await;
+            return Err(Error::Action {
+                name: actions[index].name(),
+                phase: "start",
+                cause: err,
+            });
+        }
+    }
+    for action in actions.iter_mut() {
+        action.opened(context).await;
+    }
+    Ok(())
+}
+
+/// Stops the actions in reverse order, logging failures instead of giving up.
+pub async fn stop_all(actions: &mut [Box<dyn Action>], context: &Context<'_>) {
+    for action in actions.iter_mut().rev() {
+        let result = action.stop(context).await;
+        metrics::action(
+            &context.config.profile,
+            &action.name(),
+            "stop",
+            result.is_ok(),
+        );
+        if let Err(err) = result {
+            error::report(
+                context.output,
+                &Error::Action {
+                    name: action.name(),
+                    phase: "stop",
+                    cause: err,
+                },
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RedemptionReward;
+
+    #[test]
+    fn renders_placeholders_once() {
+        let event = RedemptionEvent {
+            user_name: "Ann".to_owned(),
+            user_login: "ann".to_owned(),
+            user_input: "{user} {remaining".to_owned(),
+            reward: RedemptionReward {
+                title: "Pond".to_owned(),
+                cost: 100,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            render(
+                "{user} ({login}) opened {reward} for {cost} and {remaining}: {input} {unknown}",
+                &event,
+                Duration::from_secs(120),
+            ),
+            "Ann (ann) opened Pond for 100 and 2 minutes: {user} {remaining {unknown}"
+        );
+        assert_eq!(render("{user", &event, Duration::ZERO), "{user");
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_native_tls::{native_tls, TlsConnector};
+
+use crate::action::{self, Action};
+use crate::{send_chat_message, write_output, Config};
+
+const IRC_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn default_reminder() -> u64 {
+    60
+}
+
+/// Twitch IRC over TLS, as the login carries the user access token.
+fn default_irc_endpoint() -> String {
+    "irc.chat.twitch.tv:6697".to_owned()
+}
+
+/// Twitch's plaintext IRC port, refused so the token is never sent in the clear.
+const PLAINTEXT_IRC_PORT: &str = "6667";
+
+/// How announcements get into chat.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Via {
+    /// Helix Send Chat Message, needs the `user:write:chat` scope.
+    #[default]
+    Helix,
+    /// Twitch IRC, needs the `chat:edit` scope.
+    Irc,
+}
+
+/// Chat messages for when the window opens, is about to close and closes.
+/// Empty templates are not sent. See [`action::render`] for the placeholders.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    #[serde(default)]
+    pub on_enable: String,
+    #[serde(default)]
+    pub on_reminder: String,
+    #[serde(default)]
+    pub on_disable: String,
+    /// How long before the window closes `on_reminder` is sent.
+    #[serde(default = "default_reminder")]
+    pub reminder_secs: u64,
+    #[serde(default)]
+    pub via: Via,
+    #[serde(default = "default_irc_endpoint")]
+    pub irc_endpoint: String,
+}
+
+impl Default for Announcement {
+    fn default() -> Announcement {
+        Announcement {
+            on_enable: "{user} opened the pond for {remaining}!".to_owned(),
+            on_reminder: "The pond closes in {remaining}!".to_owned(),
+            on_disable: "The pond is closed.".to_owned(),
+            reminder_secs: default_reminder(),
+            via: Via::default(),
+            irc_endpoint: default_irc_endpoint(),
+        }
+    }
+}
+
+impl Announcement {
+    /// Checks what can be checked without sending anything.
+    pub fn validate(&self) -> Result<()> {
+        if self.via != Via::Irc {
+            return Ok(());
+        }
+        match self.irc_endpoint.rsplit_once(':') {
+            Some((_, PLAINTEXT_IRC_PORT)) => Err(anyhow!(
+                "IRC endpoint {} is not encrypted, use port 6697",
+                self.irc_endpoint
+            )),
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+            _ => Err(anyhow!(
+                "IRC endpoint \"{}\" is not host:port",
+                self.irc_endpoint
+            )),
+        }
+    }
+
+    /// Sends in the background and only logs failures, so a slow or
+    /// failing chat never holds up the other actions.
+    fn announce(&self, context: &action::Context, template: &str) {
+        if template.is_empty() {
+            return;
+        }
+        let message = context.render(template);
+        let config = context.config.clone();
+        let channel = context.event.broadcaster_user_login.clone();
+        let broadcaster_id = context.event.broadcaster_user_id.clone();
+        let output = Arc::clone(context.output);
+        let via = self.via;
+        let endpoint = self.irc_endpoint.clone();
+        tokio::spawn(async move {
+            let result = match via {
+                Via::Helix => send_chat_message(&config, &broadcaster_id, &message).await,
+                Via::Irc => timeout(
+                    IRC_TIMEOUT,
+                    send_irc(&endpoint, &config, &channel, &message),
+                )
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("IRC server did not answer in time"))),
+            };
+            log(&output, result, &message);
+        });
+    }
+}
+
+fn log(output: &Arc<Mutex<String>>, result: Result<()>, message: &str) {
+    let text = match result {
+        Ok(()) => format!("Announced \"{}\"", message),
+        Err(err) => format!("Could not announce \"{}\": {:#}", message, err),
+    };
+    write_output(output, &text);
+}
+
+/// Announces a window, but only one that really opened, so chat does not
+/// hear about windows whose other actions failed to start.
+pub struct Announcer {
+    announcement: Announcement,
+    opened: bool,
+}
+
+impl Announcer {
+    pub fn new(announcement: Announcement) -> Announcer {
+        Announcer {
+            announcement,
+            opened: false,
+        }
+    }
+}
+
+#[async_trait]
+impl Action for Announcer {
+    fn name(&self) -> String {
+        "announcements".to_owned()
+    }
+
+    async fn start(&mut self, _context: &action::Context<'_>) -> Result<()> {
+        Ok(())
+    }
+
+    async fn opened(&mut self, context: &action::Context<'_>) {
+        self.opened = true;
+        self.announcement
+            .announce(context, &self.announcement.on_enable);
+    }
+
+    async fn stop(&mut self, context: &action::Context<'_>) -> Result<()> {
+        if std::mem::take(&mut self.opened) {
+            self.announcement
+                .announce(context, &self.announcement.on_disable);
+        }
+        Ok(())
+    }
+
+    fn reminder(&self) -> Option<Duration> {
+        (!self.announcement.on_reminder.is_empty() && self.announcement.reminder_secs > 0)
+            .then(|| Duration::from_secs(self.announcement.reminder_secs))
+    }
+
+    async fn remind(&mut self, context: &action::Context<'_>) -> Result<()> {
+        self.announcement
+            .announce(context, &self.announcement.on_reminder);
+        Ok(())
+    }
+}
+
+/// Logs in as the channel itself, sends one message and leaves again. Only
+/// talks TLS, so the token is never sent in the clear.
+async fn send_irc(endpoint: &str, config: &Config, channel: &str, message: &str) -> Result<()> {
+    let host = endpoint
+        .rsplit_once(':')
+        .map(|(host, _)| host)
+        .ok_or_else(|| anyhow!("IRC endpoint \"{}\" is not host:port", endpoint))?;
+    let stream = TcpStream::connect(endpoint)
+        .await
+        .with_context(|| format!("Failed connecting to {}", endpoint))?;
+    let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+    let stream = connector
+        .connect(host, stream)
+        .await
+        .with_context(|| format!("TLS handshake with {} failed", endpoint))?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    writer
+        .write_all(
+            format!(
+                "PASS oauth:{}\r\nNICK {}\r\n",
+                config.user_access_token(),
+                channel
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let Some(line) = lines.next_line().await? else {
+            return Err(anyhow!("IRC server closed the connection while logging in"));
+        };
+        if line.contains(" 001 ") {
+            break;
+        }
+        if line.contains("NOTICE") {
+            return Err(anyhow!("IRC login failed: {}", line.trim()));
+        }
+    }
+
+    // Newlines would end the message early and start another command.
+    let message = message.replace(['\r', '\n'], " ");
+    writer
+        .write_all(format!("PRIVMSG #{} :{}\r\nQUIT\r\n", channel, message).as_bytes())
+        .await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_plaintext_irc() {
+        let mut announcement = Announcement {
+            via: Via::Irc,
+            ..Default::default()
+        };
+        announcement.validate().unwrap();
+        announcement.irc_endpoint = "irc.chat.twitch.tv:6667".to_owned();
+        assert!(announcement.validate().is_err());
+        announcement.irc_endpoint = "irc.chat.twitch.tv".to_owned();
+        assert!(announcement.validate().is_err());
+    }
+}
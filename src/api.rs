@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::io::Read;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tiny_http::{Method, Request, Response};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::history;
+use crate::metrics;
+use crate::runtime::Control;
+use crate::scheduler::Message;
+use crate::server::{self, Server};
+
+/// How long to wait for the scheduler, which may be starting actions.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const DEFAULT_REDEMPTIONS: usize = 20;
+const MAX_REDEMPTIONS: usize = 500;
+
+#[derive(Deserialize)]
+struct Enable {
+    reward_title: String,
+    /// The binding's duration if not set.
+    duration_secs: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct Disable {
+    reward_title: String,
+}
+
+/// What the API talks to, all of it going through the running channel.
+struct Api {
+    profile: String,
+    token: String,
+    scheduler: UnboundedSender<Message>,
+    control: UnboundedSender<Control>,
+    output: Arc<Mutex<String>>,
+}
+
+/// Serves the control API until dropped. Refuses to start without a token.
+pub fn serve(
+    port: u16,
+    profile: &str,
+    token: &str,
+    scheduler: UnboundedSender<Message>,
+    control: UnboundedSender<Control>,
+    output: Arc<Mutex<String>>,
+) -> Result<Server> {
+    if token.is_empty() {
+        return Err(anyhow!(
+            "Not serving the control API, it needs an API token"
+        ));
+    }
+    let api = Api {
+        profile: profile.to_owned(),
+        token: token.to_owned(),
+        scheduler,
+        control,
+        output,
+    };
+    Server::start("the control API", port, move |request, _| {
+        api.handle(request)
+    })
+}
+
+/// Compares all of both, so the time taken does not tell how much matched.
+fn same(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |differ, (a, b)| differ | (a ^ b))
+            == 0
+}
+
+impl Api {
+    fn authorized(&self, request: &Request) -> bool {
+        request.headers().iter().any(|header| {
+            header.field.equiv("Authorization")
+                && matches!(
+                    header.value.as_str().strip_prefix("Bearer "),
+                    Some(token) if same(token, &self.token)
+                )
+        })
+    }
+
+    fn handle(&self, mut request: Request) {
+        if !self.authorized(&request) {
+            return server::respond(
+                &self.output,
+                request,
+                server::error(401, "missing or wrong token"),
+            );
+        }
+        if request.method() == &Method::Get && request.url() == "/metrics" {
+            return match metrics::render() {
+                Ok(text) => server::respond(
+                    &self.output,
+                    request,
+                    Response::from_string(text)
+                        .with_header(server::header("Content-Type", "text/plain; version=0.0.4")),
+                ),
+                Err(err) => server::respond(
+                    &self.output,
+                    request,
+                    server::error(500, &format!("{:#}", err)),
+                ),
+            };
+        }
+        let url = request.url().to_owned();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let response = match (request.method(), path) {
+            (Method::Get, "/api/status") => self.status(),
+            (Method::Get, "/api/redemptions") => self.redemptions(query),
+            (Method::Post, "/api/enable") => match body::<Enable>(&mut request) {
+                Ok(enable) => self.enable(enable),
+                Err(err) => Err((400, format!("{:#}", err))),
+            },
+            (Method::Post, "/api/disable") => match body::<Disable>(&mut request) {
+                Ok(disable) => self.disable(disable),
+                Err(err) => Err((400, format!("{:#}", err))),
+            },
+            (Method::Post, "/api/resubscribe") => self.resubscribe(),
+            _ => Err((404, "not found".to_owned())),
+        };
+        match response {
+            Ok(value) => server::respond(&self.output, request, server::json(&value)),
+            Err((status, message)) => {
+                server::respond(&self.output, request, server::error(status, &message))
+            }
+        }
+    }
+
+    /// Sends a message with a reply channel to the scheduler and waits for
+    /// its answer.
+    fn ask<T>(&self, message: impl FnOnce(Sender<T>) -> Message) -> Reply<T> {
+        let (tx, rx) = mpsc::channel();
+        self.scheduler
+            .send(message(tx))
+            .map_err(|_| (503, "the channel is not listening".to_owned()))?;
+        rx.recv_timeout(REPLY_TIMEOUT)
+            .map_err(|_| (504, "the scheduler did not answer in time".to_owned()))
+    }
+
+    fn status(&self) -> Reply<serde_json::Value> {
+        let state = self.ask(Message::Status)?;
+        Ok(serde_json::json!({
+            "profile": self.profile,
+            "bindings": state.bindings,
+            "windows": state.windows,
+        }))
+    }
+
+    fn enable(&self, enable: Enable) -> Reply<serde_json::Value> {
+        let outcome = self
+            .ask(|reply| Message::Enable {
+                reward_title: enable.reward_title,
+                duration: enable.duration_secs.map(Duration::from_secs),
+                reply,
+            })?
+            .map_err(|err| (409, format!("{:#}", err)))?;
+        Ok(serde_json::json!({ "outcome": outcome }))
+    }
+
+    fn disable(&self, disable: Disable) -> Reply<serde_json::Value> {
+        self.ask(|reply| Message::Disable {
+            reward_title: disable.reward_title,
+            reply,
+        })?
+        .map_err(|err| (409, format!("{:#}", err)))?;
+        Ok(serde_json::json!({ "outcome": "closed" }))
+    }
+
+    /// Newest first.
+    fn redemptions(&self, query: &str) -> Reply<serde_json::Value> {
+        let limit = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("limit="))
+            .map(|limit| {
+                limit
+                    .parse::<usize>()
+                    .map_err(|_| (400, format!("invalid limit \"{}\"", limit)))
+            })
+            .transpose()?
+            .unwrap_or(DEFAULT_REDEMPTIONS)
+            .min(MAX_REDEMPTIONS);
+        let entries = history::load(&self.profile).map_err(|err| (500, format!("{:#}", err)))?;
+        let recent: Vec<_> = entries.iter().rev().take(limit).collect();
+        Ok(serde_json::json!({ "redemptions": recent }))
+    }
+
+    /// Only asks for it, how it went shows up in the log.
+    fn resubscribe(&self) -> Reply<serde_json::Value> {
+        self.control
+            .send(Control::Resubscribe)
+            .map_err(|_| (503, "the channel is not listening".to_owned()))?;
+        Ok(serde_json::json!({ "outcome": "resubscribing" }))
+    }
+}
+
+/// The status code and message of a failed request.
+type Reply<T> = std::result::Result<T, (u16, String)>;
+
+fn body<T: for<'de> Deserialize<'de>>(request: &mut Request) -> Result<T> {
+    serde_json::from_reader(request.as_reader().take(64 * 1024))
+        .map_err(|err| anyhow!("invalid request body: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_tokens() {
+        assert!(same("secret", "secret"));
+        assert!(!same("secreT", "secret"));
+        assert!(!same("secret", "secret2"));
+        assert!(!same("", "secret"));
+        assert!(same("", ""));
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::action::{self, Action};
+use crate::error::Error;
+use crate::nightbot::Nightbot;
+use crate::{snapshot, CommandRef, Config, StreamElements};
+
+/// The chat bot whose commands a binding works on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Bot {
+    #[default]
+    #[serde(rename = "streamelements")]
+    StreamElements,
+    #[serde(rename = "nightbot")]
+    Nightbot,
+}
+
+impl std::fmt::Display for Bot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bot::StreamElements => write!(f, "StreamElements"),
+            Bot::Nightbot => write!(f, "Nightbot"),
+        }
+    }
+}
+
+/// A command as the bot reported it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BotCommand {
+    pub bot: Bot,
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    /// The bot's own representation, which [`ChatBot::restore`] puts back.
+    pub raw: Value,
+}
+
+impl BotCommand {
+    pub fn command_ref(&self) -> CommandRef {
+        CommandRef {
+            command_name: self.name.clone(),
+            command_id: self.id.clone(),
+        }
+    }
+}
+
+/// What the actions need from a chat bot's command API.
+#[async_trait]
+pub trait ChatBot: Send + Sync {
+    fn bot(&self) -> Bot;
+    /// Finds a command by its ID if known, by name otherwise.
+    async fn find(&self, command: &CommandRef) -> Result<BotCommand>;
+    async fn enable(&self, command: &CommandRef) -> Result<BotCommand>;
+    async fn disable(&self, command: &CommandRef) -> Result<BotCommand>;
+    /// Puts back everything fishinge may have changed about the command.
+    async fn restore(&self, original: &BotCommand) -> Result<BotCommand>;
+}
+
+/// The clients of the bots the bindings of a channel use.
+pub struct Bots {
+    pub streamelements: Option<StreamElements>,
+    pub nightbot: Option<Nightbot>,
+}
+
+impl Bots {
+    /// Connects to each bot some binding uses, which also checks their credentials.
+    pub async fn connect(config: &Config) -> std::result::Result<Bots, Error> {
+        Ok(Bots {
+            streamelements: match config.uses(Bot::StreamElements) {
+                true => Some(
+                    StreamElements::connect(config)
+                        .await
+                        .map_err(Error::StreamElementsApi)?,
+                ),
+                false => None,
+            },
+            nightbot: match config.uses(Bot::Nightbot) {
+                true => Some(
+                    Nightbot::connect(config)
+                        .await
+                        .map_err(Error::NightbotApi)?,
+                ),
+                false => None,
+            },
+        })
+    }
+
+    pub fn get(&self, bot: Bot) -> Result<&dyn ChatBot> {
+        let client: Option<&dyn ChatBot> = match bot {
+            Bot::StreamElements => self.streamelements.as_ref().map(|client| client as _),
+            Bot::Nightbot => self.nightbot.as_ref().map(|client| client as _),
+        };
+        client.ok_or_else(|| anyhow!("not connected to {}", bot))
+    }
+
+    pub fn streamelements(&self) -> Result<&StreamElements> {
+        self.streamelements
+            .as_ref()
+            .ok_or_else(|| anyhow!("not connected to StreamElements"))
+    }
+}
+
+/// Enables a command of the binding's bot while the window is open.
+pub struct Toggle {
+    command: CommandRef,
+    original: Option<BotCommand>,
+}
+
+impl Toggle {
+    pub fn new(command: CommandRef) -> Toggle {
+        Toggle {
+            command,
+            original: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Action for Toggle {
+    fn name(&self) -> String {
+        format!("!{}", self.command.command_name)
+    }
+
+    async fn start(&mut self, context: &action::Context<'_>) -> Result<()> {
+        let bot = context.bot()?;
+        let profile = &context.config.profile;
+        let original = snapshot::keep(profile, &bot.find(&self.command).await?)?;
+        let command = match bot.enable(&original.command_ref()).await {
+            Ok(command) => command,
+            Err(err) => {
+                snapshot::forget(profile, &original)?;
+                return Err(err);
+            }
+        };
+        context.log(&format!("Enabled command !{}!", command.name));
+        self.original = Some(original);
+        Ok(())
+    }
+
+    async fn stop(&mut self, context: &action::Context<'_>) -> Result<()> {
+        let Some(original) = self.original.take() else {
+            return Ok(());
+        };
+        let command = context.bot()?.disable(&original.command_ref()).await?;
+        snapshot::forget(&context.config.profile, &original)?;
+        context.log(&format!("Disabled command !{}!", command.name));
+        Ok(())
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{anyhow, Context, Result};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{read_to_string, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+pub const FILE_NAME: &str = "credentials.toml";
+
+/// Overrides for the secrets, e.g. for running without the setup window.
+pub const TOKEN_VAR: &str = "FISHINGE_USER_ACCESS_TOKEN";
+pub const JWT_VAR: &str = "FISHINGE_JWT";
+pub const NIGHTBOT_TOKEN_VAR: &str = "FISHINGE_NIGHTBOT_TOKEN";
+pub const API_TOKEN_VAR: &str = "FISHINGE_API_TOKEN";
+pub const PASSPHRASE_VAR: &str = "FISHINGE_PASSPHRASE";
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    #[serde(default)]
+    pub user_access_token: String,
+    #[serde(default)]
+    pub jwt: String,
+    #[serde(default)]
+    pub nightbot_token: String,
+    /// Required from callers of the local control API.
+    #[serde(default)]
+    pub api_token: String,
+    /// Secrets of the webhooks, by the name the webhooks refer to them with.
+    #[serde(default)]
+    pub webhook_secrets: BTreeMap<String, String>,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("user_access_token", &redacted(&self.user_access_token))
+            .field("jwt", &redacted(&self.jwt))
+            .field("nightbot_token", &redacted(&self.nightbot_token))
+            .field("api_token", &redacted(&self.api_token))
+            .field("webhook_secrets", &self.webhook_secrets.keys())
+            .finish()
+    }
+}
+
+/// Stand-in for a secret in `Debug` output and error messages.
+pub fn redacted(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        "<empty>"
+    } else {
+        "<redacted>"
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CredentialsFile {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    user_access_token: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    jwt: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    nightbot_token: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    api_token: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    webhook_secrets: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted: Option<Encrypted>,
+}
+
+/// Credentials sealed with XChaCha20-Poly1305, keyed by Argon2id over the passphrase.
+#[derive(Serialize, Deserialize)]
+struct Encrypted {
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+pub enum Loaded {
+    Missing,
+    Plain(Credentials),
+    Decrypted(Credentials),
+    /// The file is encrypted and no passphrase was given.
+    Locked,
+}
+
+pub fn load(dir: &Path, passphrase: Option<&str>) -> Result<Loaded> {
+    let path = dir.join(FILE_NAME);
+    if !path.exists() {
+        return Ok(Loaded::Missing);
+    }
+    let data = read_to_string(&path)
+        .with_context(|| format!("Failed to read credentials file from {:?}", &path))?;
+    let file: CredentialsFile = toml::from_str(&data)
+        .with_context(|| format!("Could not parse credentials file {:?}", &path))?;
+    let Some(encrypted) = file.encrypted else {
+        return Ok(Loaded::Plain(Credentials {
+            user_access_token: file.user_access_token,
+            jwt: file.jwt,
+            nightbot_token: file.nightbot_token,
+            api_token: file.api_token,
+            webhook_secrets: file.webhook_secrets,
+        }));
+    };
+    match passphrase {
+        Some(passphrase) if !passphrase.is_empty() => {
+            Ok(Loaded::Decrypted(decrypt(&encrypted, passphrase)?))
+        }
+        _ => Ok(Loaded::Locked),
+    }
+}
+
+/// Writes the credentials with owner-only permissions, encrypted if a
+/// passphrase is given.
+pub fn write(dir: &Path, credentials: &Credentials, passphrase: &str) -> Result<()> {
+    let file = if passphrase.is_empty() {
+        CredentialsFile {
+            user_access_token: credentials.user_access_token.clone(),
+            jwt: credentials.jwt.clone(),
+            nightbot_token: credentials.nightbot_token.clone(),
+            api_token: credentials.api_token.clone(),
+            webhook_secrets: credentials.webhook_secrets.clone(),
+            encrypted: None,
+        }
+    } else {
+        CredentialsFile {
+            encrypted: Some(encrypt(credentials, passphrase)?),
+            ..Default::default()
+        }
+    };
+
+    let path = dir.join(FILE_NAME);
+    let mut file_handle = create_private(&path)
+        .with_context(|| format!("Failed to open credentials file {:?}", &path))?;
+    file_handle.write_all(toml::to_string_pretty(&file)?.as_bytes())?;
+    file_handle.flush()?;
+    Ok(())
+}
+
+/// Creates or truncates a file only the owner can read, for anything that
+/// may hold secrets.
+pub fn create_private(path: &Path) -> Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).truncate(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        if path.exists() {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    Ok(options.open(path)?)
+}
+
+/// Applies the environment variable overrides, returning the names of the
+/// fields they set.
+pub fn apply_env(credentials: &mut Credentials) -> HashSet<&'static str> {
+    apply_overrides(credentials, |name| std::env::var(name).ok())
+}
+
+/// Like [`apply_env`], but reads the variables from `var`.
+pub fn apply_overrides(
+    credentials: &mut Credentials,
+    var: impl Fn(&str) -> Option<String>,
+) -> HashSet<&'static str> {
+    let mut applied = HashSet::new();
+    if let Some(token) = var(TOKEN_VAR) {
+        credentials.user_access_token = token;
+        applied.insert("user_access_token");
+    }
+    if let Some(jwt) = var(JWT_VAR) {
+        credentials.jwt = jwt;
+        applied.insert("jwt");
+    }
+    if let Some(token) = var(NIGHTBOT_TOKEN_VAR) {
+        credentials.nightbot_token = token;
+        applied.insert("nightbot_token");
+    }
+    if let Some(token) = var(API_TOKEN_VAR) {
+        credentials.api_token = token;
+        applied.insert("api_token");
+    }
+    applied
+}
+
+/// What to write to the file: the overridden fields keep their stored
+/// values, so secrets only set in the environment never end up on disk.
+pub fn to_store(
+    current: &Credentials,
+    stored: &Credentials,
+    overridden: &HashSet<&'static str>,
+) -> Credentials {
+    let pick = |field: &str, current: &String, stored: &String| {
+        if overridden.contains(field) {
+            stored.clone()
+        } else {
+            current.clone()
+        }
+    };
+    Credentials {
+        user_access_token: pick(
+            "user_access_token",
+            &current.user_access_token,
+            &stored.user_access_token,
+        ),
+        jwt: pick("jwt", &current.jwt, &stored.jwt),
+        nightbot_token: pick(
+            "nightbot_token",
+            &current.nightbot_token,
+            &stored.nightbot_token,
+        ),
+        api_token: pick("api_token", &current.api_token, &stored.api_token),
+        webhook_secrets: current.webhook_secrets.clone(),
+    }
+}
+
+pub fn env_passphrase() -> Option<String> {
+    std::env::var(PASSPHRASE_VAR).ok()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|err| anyhow!("could not derive key from passphrase: {}", err))?;
+    Ok(key)
+}
+
+fn encrypt(credentials: &Credentials, passphrase: &str) -> Result<Encrypted> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new(&key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let plaintext = toml::to_string(credentials)?;
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| anyhow!("could not encrypt credentials"))?;
+    Ok(Encrypted {
+        salt: STANDARD.encode(salt),
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+    })
+}
+
+fn decrypt(encrypted: &Encrypted, passphrase: &str) -> Result<Credentials> {
+    let salt = STANDARD
+        .decode(&encrypted.salt)
+        .context("Credentials file has an invalid salt")?;
+    let nonce = STANDARD
+        .decode(&encrypted.nonce)
+        .context("Credentials file has an invalid nonce")?;
+    if nonce.len() != 24 {
+        return Err(anyhow!("credentials file has an invalid nonce"));
+    }
+    let ciphertext = STANDARD
+        .decode(&encrypted.ciphertext)
+        .context("Credentials file has invalid ciphertext")?;
+    let key = derive_key(passphrase, &salt)?;
+    let cipher = XChaCha20Poly1305::new(&key.into());
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| anyhow!("wrong passphrase for the credentials file"))?;
+    let plaintext = String::from_utf8(plaintext).context("Decrypted credentials are not UTF-8")?;
+    toml::from_str(&plaintext).context("Could not parse decrypted credentials")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_credentials_need_the_passphrase() {
+        let credentials = Credentials {
+            user_access_token: "token".to_owned(),
+            jwt: "jwt".to_owned(),
+            nightbot_token: String::new(),
+            api_token: "api".to_owned(),
+            webhook_secrets: BTreeMap::from([("pond".to_owned(), "hmac".to_owned())]),
+        };
+        let encrypted = encrypt(&credentials, "passphrase").unwrap();
+        assert!(!encrypted.ciphertext.contains("token"));
+        assert_eq!(decrypt(&encrypted, "passphrase").unwrap(), credentials);
+        assert!(decrypt(&encrypted, "wrong").is_err());
+    }
+}
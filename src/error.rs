@@ -0,0 +1,160 @@
+use reqwest::StatusCode;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::write_output;
+
+/// What went wrong, by where it went wrong, so the log can tell the
+/// streamer what to look at. The cause keeps the details.
+#[derive(Debug)]
+pub enum Error {
+    /// The config or credentials cannot be used.
+    Config(anyhow::Error),
+    /// Twitch refused the client ID or user access token.
+    Auth(anyhow::Error),
+    /// Any other failed Helix request.
+    TwitchApi(anyhow::Error),
+    StreamElementsApi(anyhow::Error),
+    NightbotApi(anyhow::Error),
+    /// The EventSub connection could not be made or went away.
+    EventSub(anyhow::Error),
+    /// An action of a binding failed to start, stop or remind.
+    Action {
+        name: String,
+        /// `start`, `stop` or `remind`, like in the metrics.
+        phase: &'static str,
+        cause: anyhow::Error,
+    },
+    /// A binding's script did not compile or failed on a redemption.
+    Script(anyhow::Error),
+    /// The history or config could not be written.
+    Storage(anyhow::Error),
+    /// The overlay or control API could not answer a request.
+    Serve(anyhow::Error),
+    /// A part of fishinge stopped when it should not have.
+    Internal(String),
+}
+
+impl Error {
+    /// Sorts a failed Twitch request into [`Error::Auth`] if Twitch
+    /// answered 401 Unauthorized, or [`Error::TwitchApi`] otherwise.
+    pub fn twitch(cause: anyhow::Error) -> Error {
+        let unauthorized = cause.chain().any(|err| {
+            matches!(
+                err.downcast_ref::<reqwest::Error>()
+                    .and_then(|err| err.status()),
+                Some(StatusCode::UNAUTHORIZED)
+            )
+        });
+        match unauthorized {
+            true => Error::Auth(cause),
+            false => Error::TwitchApi(cause),
+        }
+    }
+
+    /// What the streamer can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Error::Config(_) => {
+                "Fix the settings and apply them, or edit the profile's config files."
+            }
+            Error::Auth(_) => {
+                "The user access token may have expired or be missing a scope, get a new one as the README describes."
+            }
+            Error::TwitchApi(_) => {
+                "Check the streamer and reward titles match Twitch exactly. If they do, Twitch may be having trouble."
+            }
+            Error::StreamElementsApi(_) => {
+                "Check the StreamElements JWT, it changes when it is reset on the dashboard."
+            }
+            Error::NightbotApi(_) => "Check the Nightbot access token, it may have expired.",
+            Error::EventSub(_) => "Check the internet connection, then start listening again.",
+            Error::Action { .. } => "Check the action's settings in its binding.",
+            Error::Script(_) => "Check the binding's script, what it printed is in the log.",
+            Error::Storage(_) => {
+                "Check the disk is not full and the profile's directory can be written."
+            }
+            Error::Serve(_) => {
+                "Check the browser source or tool using the overlay or control API stays connected."
+            }
+            Error::Internal(_) => "This is a bug, please report it along with the log.",
+        }
+    }
+}
+
+/// The alternate form, `{:#}`, adds the causes like anyhow does.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = match self {
+            Error::Config(cause) => {
+                write!(f, "Config error")?;
+                cause
+            }
+            Error::Auth(cause) => {
+                write!(f, "Twitch refused the credentials")?;
+                cause
+            }
+            Error::TwitchApi(cause) => {
+                write!(f, "Twitch API request failed")?;
+                cause
+            }
+            Error::StreamElementsApi(cause) => {
+                write!(f, "StreamElements API request failed")?;
+                cause
+            }
+            Error::NightbotApi(cause) => {
+                write!(f, "Nightbot API request failed")?;
+                cause
+            }
+            Error::EventSub(cause) => {
+                write!(f, "EventSub connection failed")?;
+                cause
+            }
+            Error::Action { name, phase, cause } => {
+                write!(f, "Could not {} {}", phase, name)?;
+                cause
+            }
+            Error::Script(cause) => {
+                write!(f, "Script error")?;
+                cause
+            }
+            Error::Storage(cause) => {
+                write!(f, "Saving failed")?;
+                cause
+            }
+            Error::Serve(cause) => {
+                write!(f, "Local request failed")?;
+                cause
+            }
+            Error::Internal(reason) => return write!(f, "{}", reason),
+        };
+        if f.alternate() {
+            write!(f, ": {:#}", cause)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Config(cause)
+            | Error::Auth(cause)
+            | Error::TwitchApi(cause)
+            | Error::StreamElementsApi(cause)
+            | Error::NightbotApi(cause)
+            | Error::EventSub(cause)
+            | Error::Script(cause)
+            | Error::Storage(cause)
+            | Error::Serve(cause)
+            | Error::Action { cause, .. } => Some(cause.as_ref()),
+            Error::Internal(_) => None,
+        }
+    }
+}
+
+/// Logs the error with its hint. Everything that stops a channel or a
+/// redemption ends up here.
+pub fn report(output: &Arc<Mutex<String>>, error: &Error) {
+    write_output(output, &format!("{:#}\n  Hint: {}", error, error.hint()));
+}
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Weekday};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+/// How often deferred redemptions are checked again, and how long a looked
+/// up live status is trusted.
+pub const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What happens to a redemption while its binding is closed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    /// Keep it until the binding opens, as long as fishinge keeps running.
+    #[default]
+    Defer,
+    Refund,
+    /// Record it as rejected without giving the points back.
+    Reject,
+}
+
+/// Times are local to the machine fishinge runs on, as `HH:MM`. A window
+/// ending before it starts runs past midnight.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleWindow {
+    /// Days the window starts on, every day if empty.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    pub start: String,
+    pub end: String,
+}
+
+impl Default for ScheduleWindow {
+    fn default() -> ScheduleWindow {
+        ScheduleWindow {
+            days: Vec::new(),
+            start: "18:00".to_owned(),
+            end: "23:00".to_owned(),
+        }
+    }
+}
+
+impl ScheduleWindow {
+    fn times(&self) -> Result<(NaiveTime, NaiveTime)> {
+        let parse = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .map_err(|_| anyhow!("\"{}\" is not a time like 18:30", time))
+        };
+        Ok((parse(&self.start)?, parse(&self.end)?))
+    }
+
+    fn on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Result<bool> {
+        let (start, end) = self.times()?;
+        let time = now.time();
+        let today = now.weekday();
+        Ok(if start <= end {
+            self.on(today) && start <= time && time < end
+        } else {
+            (self.on(today) && start <= time) || (self.on(today.pred()) && time < end)
+        })
+    }
+}
+
+/// When a binding is open for redemptions.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Gate {
+    #[serde(default)]
+    pub require_live: bool,
+    #[serde(default)]
+    pub otherwise: Fallback,
+    /// Open during any of these windows, always if empty. Kept last, as TOML
+    /// has no plain values after a table.
+    #[serde(default)]
+    pub schedule: Vec<ScheduleWindow>,
+}
+
+impl Gate {
+    pub fn is_always_open(&self) -> bool {
+        !self.require_live && self.schedule.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for window in &self.schedule {
+            window.times()?;
+        }
+        Ok(())
+    }
+
+    /// Why the binding is closed right now, if it is. The live status is only
+    /// looked up when needed.
+    pub async fn closed_reason(
+        &self,
+        now: DateTime<Local>,
+        is_live: impl Future<Output = Result<bool>>,
+    ) -> Option<String> {
+        if !self.schedule.is_empty() {
+            let mut open = false;
+            for window in &self.schedule {
+                match window.contains(&now) {
+                    Ok(true) => open = true,
+                    Ok(false) => {}
+                    Err(err) => return Some(format!("invalid schedule: {:#}", err)),
+                }
+            }
+            if !open {
+                return Some("outside the schedule".to_owned());
+            }
+        }
+        if self.require_live {
+            match is_live.await {
+                Ok(true) => {}
+                Ok(false) => return Some("the stream is offline".to_owned()),
+                Err(err) => return Some(format!("could not check if live: {:#}", err)),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn at(day: u32, time: &str) -> DateTime<Utc> {
+        // 2024-01-01 was a Monday.
+        format!("2024-01-{:02}T{}:00Z", day, time).parse().unwrap()
+    }
+
+    #[test]
+    fn window_across_midnight_belongs_to_its_start_day() {
+        let window = ScheduleWindow {
+            days: vec![Weekday::Fri],
+            start: "22:00".to_owned(),
+            end: "02:00".to_owned(),
+        };
+        assert!(window.contains(&at(5, "23:30")).unwrap());
+        assert!(window.contains(&at(6, "01:59")).unwrap());
+        assert!(!window.contains(&at(6, "02:00")).unwrap());
+        assert!(!window.contains(&at(6, "23:30")).unwrap());
+        assert!(!window.contains(&at(5, "01:00")).unwrap());
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let window = ScheduleWindow::default();
+        assert!(window.contains(&at(1, "18:00")).unwrap());
+        assert!(!window.contains(&at(1, "23:00")).unwrap());
+        assert!(!window.contains(&at(1, "17:59")).unwrap());
+    }
+}
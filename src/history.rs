@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::{profile, RedemptionEvent};
+
+/// One JSON object per line, appended next to the config of each profile.
+pub const FILE_NAME: &str = "history.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", content = "reason", rename_all = "snake_case")]
+pub enum Outcome {
+    /// The command was enabled for a new window.
+    Enabled,
+    /// An already open window was extended.
+    Extended,
+    Failed(String),
+    /// No binding matched the reward.
+    Unbound,
+    /// A limit of the binding was hit.
+    Rejected {
+        reason: String,
+        refunded: bool,
+    },
+}
+
+impl Outcome {
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Outcome::Failed(_))
+    }
+
+    /// Whether the redemption opened or extended a window.
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, Outcome::Enabled | Outcome::Extended)
+    }
+
+    pub fn is_refunded(&self) -> bool {
+        matches!(self, Outcome::Rejected { refunded: true, .. })
+    }
+
+    /// Without the reason, e.g. for metric labels.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Outcome::Enabled => "enabled",
+            Outcome::Extended => "extended",
+            Outcome::Failed(_) => "failed",
+            Outcome::Unbound => "unbound",
+            Outcome::Rejected { refunded: true, .. } => "refunded",
+            Outcome::Rejected {
+                refunded: false, ..
+            } => "rejected",
+        }
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Enabled => write!(f, "enabled"),
+            Outcome::Extended => write!(f, "extended"),
+            Outcome::Failed(reason) => write!(f, "failed: {}", reason),
+            Outcome::Unbound => write!(f, "no binding"),
+            Outcome::Rejected {
+                reason,
+                refunded: true,
+            } => write!(f, "refunded: {}", reason),
+            Outcome::Rejected {
+                reason,
+                refunded: false,
+            } => write!(f, "rejected: {}", reason),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub redemption_id: String,
+    pub redeemed_at: DateTime<Utc>,
+    pub recorded_at: DateTime<Utc>,
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    pub reward_title: String,
+    pub cost: u64,
+    #[serde(default)]
+    pub user_input: String,
+    pub outcome: Outcome,
+    /// How long the command was enabled for because of this redemption.
+    pub duration_secs: u64,
+}
+
+impl Entry {
+    pub fn new(event: &RedemptionEvent, outcome: Outcome, duration: Duration) -> Entry {
+        let recorded_at = Utc::now();
+        Entry {
+            redemption_id: event.id.clone(),
+            redeemed_at: DateTime::parse_from_rfc3339(&event.redeemed_at)
+                .map(|redeemed_at| redeemed_at.with_timezone(&Utc))
+                .unwrap_or(recorded_at),
+            recorded_at,
+            user_id: event.user_id.clone(),
+            user_login: event.user_login.clone(),
+            user_name: event.user_name.clone(),
+            reward_title: event.reward.title.clone(),
+            cost: event.reward.cost,
+            user_input: event.user_input.clone(),
+            outcome,
+            duration_secs: duration.as_secs(),
+        }
+    }
+}
+
+pub fn path(profile: &str) -> Result<PathBuf> {
+    Ok(profile::dir(profile)?.join(FILE_NAME))
+}
+
+pub fn append(profile: &str, entry: &Entry) -> Result<()> {
+    let path = path(profile)?;
+    if let Some(dir) = path.parent() {
+        DirBuilder::new().recursive(true).create(dir)?;
+    }
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .with_context(|| format!("Failed to write history to {:?}", &path))
+}
+
+/// All entries of a profile, oldest first. Lines that cannot be read, e.g.
+/// one cut short by a crash, are skipped.
+pub fn load(profile: &str) -> Result<Vec<Entry>> {
+    let path = path(profile)?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let data = read_to_string(&path)
+        .with_context(|| format!("Failed to read history from {:?}", &path))?;
+    Ok(data
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+pub fn modified(profile: &str) -> Option<SystemTime> {
+    path(profile).ok()?.metadata().ok()?.modified().ok()
+}
+
+/// Viewers choose their names and input, so text a spreadsheet would run
+/// as a formula is prefixed with `'`.
+fn cell(value: &str) -> String {
+    match value.chars().next() {
+        Some('=' | '+' | '-' | '@' | '\t' | '\r') => format!("'{}", value),
+        _ => value.to_owned(),
+    }
+}
+
+pub fn export_csv(entries: &[Entry], path: &Path) -> Result<()> {
+    let mut writer =
+        csv::Writer::from_path(path).with_context(|| format!("Failed to create {:?}", path))?;
+    writer.write_record([
+        "redeemed_at",
+        "user_name",
+        "user_login",
+        "reward_title",
+        "cost",
+        "user_input",
+        "outcome",
+        "duration_secs",
+    ])?;
+    for entry in entries {
+        writer.write_record([
+            entry.redeemed_at.to_rfc3339(),
+            cell(&entry.user_name),
+            cell(&entry.user_login),
+            cell(&entry.reward_title),
+            entry.cost.to_string(),
+            cell(&entry.user_input),
+            entry.outcome.to_string(),
+            entry.duration_secs.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formulas_are_not_exported() {
+        assert_eq!(cell("=HYPERLINK(\"x\")"), "'=HYPERLINK(\"x\")");
+        assert_eq!(cell("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(cell("-1+1"), "'-1+1");
+        assert_eq!(cell("fish, \"please\""), "fish, \"please\"");
+    }
+}
@@ -1,62 +1,351 @@
 use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use serde_json::{Map, Number, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::{read_to_string, DirBuilder, OpenOptions};
 use std::io::Write;
-use std::sync::{Arc, Mutex};
-use std::thread::sleep;
-use std::time::Duration;
-
-#[macro_use]
-pub mod macros;
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+pub mod action;
+pub mod announce;
+pub mod api;
+pub mod chatbot;
+pub mod credentials;
+pub mod error;
+pub mod gating;
+pub mod history;
+pub mod limits;
+pub mod metrics;
+pub mod migration;
+pub mod nightbot;
+pub mod overlay;
+pub mod profile;
+pub mod program;
+pub mod runtime;
+pub mod scheduler;
+pub mod script;
+pub mod server;
+pub mod snapshot;
+pub mod stats;
+pub mod streamelements;
+#[cfg(test)]
+mod testing;
+pub mod validation;
+pub mod webhook;
+
+pub use streamelements::{list_commands, CommandRef, CommandResponse, Cooldown, StreamElements};
+
+use action::ActionConfig;
+use chatbot::{Bot, Bots};
+use credentials::{redacted, Credentials, Loaded};
+use gating::Gate;
+use limits::Limits;
+use metrics::Tracked;
+
+pub const CONFIG_FILE: &str = "fishinge.conf";
+
+/// Secrets are never written to the config file itself, see [`credentials`].
+/// They are still read from it so that older config files keep working.
+///
+/// Fields added after version 1 need a serde default, older layouts are
+/// upgraded in [`migration`].
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Config {
+    #[serde(default = "migration::current_version")]
+    pub version: u32,
     pub client_id: String,
     pub streamer: String,
+    #[serde(default, skip_serializing)]
     pub user_access_token: String,
-    pub reward_title: String,
+    #[serde(default, skip_serializing)]
     pub jwt: String,
-    pub command_name: String,
+    #[serde(default, skip_serializing)]
+    pub nightbot_token: String,
+    #[serde(default, skip_serializing)]
+    pub api_token: String,
+    /// Kept in the credentials file, see [`webhook::Webhook::secret`].
+    #[serde(default, skip_serializing)]
+    pub webhook_secrets: BTreeMap<String, String>,
+    /// Local port the OBS overlay is served on, none if 0.
+    #[serde(default)]
+    pub overlay_port: u16,
+    /// Local port the control API is served on, none if 0.
+    #[serde(default)]
+    pub api_port: u16,
+    #[serde(default)]
+    pub bindings: Vec<Binding>,
+    /// Encrypts the credentials file when not empty.
+    #[serde(skip)]
+    pub passphrase: String,
+    /// Set when the credentials file is encrypted and was not unlocked yet.
+    #[serde(skip)]
+    pub credentials_locked: bool,
+    /// The profile this config is loaded from and written to.
+    #[serde(skip)]
+    pub profile: String,
+    /// What loading the config migrated, to be logged once.
+    #[serde(skip)]
+    pub migrated: Option<String>,
+    /// Secrets set by environment variables, which are not written back.
+    #[serde(skip)]
+    pub overridden: HashSet<&'static str>,
+    /// The credentials as read from the file, written in place of the
+    /// overridden ones.
+    #[serde(skip)]
+    stored: Credentials,
+}
+
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("version", &self.version)
+            .field("client_id", &self.client_id)
+            .field("streamer", &self.streamer)
+            .field("user_access_token", &redacted(&self.user_access_token))
+            .field("jwt", &redacted(&self.jwt))
+            .field("nightbot_token", &redacted(&self.nightbot_token))
+            .field("api_token", &redacted(&self.api_token))
+            .field("webhook_secrets", &self.webhook_secrets.keys())
+            .field("overlay_port", &self.overlay_port)
+            .field("api_port", &self.api_port)
+            .field("bindings", &self.bindings)
+            .field("passphrase", &redacted(&self.passphrase))
+            .field("credentials_locked", &self.credentials_locked)
+            .field("profile", &self.profile)
+            .field("migrated", &self.migrated)
+            .field("overridden", &self.overridden)
+            .finish()
+    }
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
+            version: migration::CURRENT_VERSION,
             client_id: "".to_owned(),
             streamer: "chobo".to_owned(),
             user_access_token: "".to_owned(),
-            reward_title: "5 Minute Fishing Trip".to_owned(),
             jwt: "".to_owned(),
-            command_name: "fishinge".to_owned(),
+            nightbot_token: "".to_owned(),
+            api_token: "".to_owned(),
+            webhook_secrets: BTreeMap::new(),
+            overlay_port: 0,
+            api_port: 0,
+            bindings: vec![Binding::default()],
+            passphrase: "".to_owned(),
+            credentials_locked: false,
+            profile: profile::DEFAULT.to_owned(),
+            migrated: None,
+            overridden: HashSet::new(),
+            stored: Credentials::default(),
+        }
+    }
+}
+
+/// Connects a channel point reward to the actions it runs.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub reward_title: String,
+    /// The chat bot whose commands the actions toggle.
+    #[serde(default)]
+    pub bot: Bot,
+    #[serde(default = "default_duration")]
+    pub duration_secs: u64,
+    /// Rhai script in the profile's directory deciding about each
+    /// redemption, see [`script`]. Accepts everything if empty.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub script: String,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub gate: Gate,
+    /// Started in order when the window opens, stopped in reverse when it closes.
+    #[serde(default)]
+    pub actions: Vec<ActionConfig>,
+}
+
+fn default_duration() -> u64 {
+    300
+}
+
+impl Default for Binding {
+    fn default() -> Binding {
+        Binding {
+            reward_title: "5 Minute Fishing Trip".to_owned(),
+            bot: Bot::default(),
+            duration_secs: default_duration(),
+            script: String::new(),
+            limits: Limits::default(),
+            gate: Gate::default(),
+            actions: vec![ActionConfig::default()],
+        }
+    }
+}
+
+impl Binding {
+    pub fn reward_title(&self) -> &str {
+        &self.reward_title
+    }
+
+    /// The actions, for the log and the UI.
+    pub fn describe(&self) -> String {
+        if self.actions.is_empty() {
+            return "nothing".to_owned();
         }
+        self.actions
+            .iter()
+            .map(ActionConfig::describe)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration_secs)
     }
 }
 
 impl Config {
-    fn get_filepath() -> Result<std::path::PathBuf> {
-        let mut config_dir =
-            dirs::config_dir().ok_or_else(|| anyhow!("could not find config dir"))?;
-        config_dir.push("fishinge");
-        Ok(config_dir)
+    fn get_filepath(&self) -> Result<std::path::PathBuf> {
+        profile::dir(&self.profile)
     }
 
+    /// Loads the default profile, see [`Config::load_profile`].
     pub fn load() -> Result<Config> {
-        let mut config_file = Config::get_filepath()?;
-        config_file.push("fishinge.conf");
+        Config::load_profile(profile::DEFAULT)
+    }
+
+    /// Loads a profile, using the passphrase from the environment, if any,
+    /// to unlock encrypted credentials.
+    pub fn load_profile(profile: &str) -> Result<Config> {
+        Config::load_with_passphrase(profile, credentials::env_passphrase().as_deref())
+    }
+
+    /// Whether a profile has a config file, even if it cannot be loaded.
+    pub fn exists(profile: &str) -> bool {
+        profile::dir(profile)
+            .map(|dir| dir.join(CONFIG_FILE).exists())
+            .unwrap_or(false)
+    }
+
+    /// A fresh config for a profile that has not been written yet.
+    pub fn new_profile(profile: &str) -> Result<Config> {
+        profile::validate_name(profile)?;
+        Ok(Config {
+            profile: profile.to_owned(),
+            ..Default::default()
+        })
+    }
+
+    pub fn load_with_passphrase(profile: &str, passphrase: Option<&str>) -> Result<Config> {
+        let mut config_file = profile::dir(profile)?;
+        config_file.push(CONFIG_FILE);
         let config_data = read_to_string(&config_file)
             .with_context(|| format!("Failed to read config file from {:?}", &config_file))?;
-        toml::from_str(&config_data)
-            .with_context(|| format!("Could not read config data from string: {}", &config_data))
+        let mut value: toml::Value = toml::from_str(&config_data)
+            .with_context(|| format!("Could not parse config file {:?}", &config_file))?;
+        let version = migration::migrate(&mut value)
+            .with_context(|| format!("Could not migrate config file {:?}", &config_file))?;
+        let mut config: Config = value
+            .try_into()
+            .with_context(|| format!("Could not read config file {:?}", &config_file))?;
+        config.profile = profile.to_owned();
+        config.load_credentials(passphrase)?;
+
+        // Rewriting a locked config would drop secrets still stored in it.
+        if version < migration::CURRENT_VERSION && !config.credentials_locked {
+            let backup = migration::backup(&config_file, &format!("v{}", version))?;
+            config.write()?;
+            config.migrated = Some(format!(
+                "Migrated config from version {} to {}, the old file is kept as {:?}",
+                version,
+                migration::CURRENT_VERSION,
+                backup
+            ));
+        }
+        Ok(config)
+    }
+
+    fn load_credentials(&mut self, passphrase: Option<&str>) -> Result<()> {
+        match credentials::load(&self.get_filepath()?, passphrase)? {
+            Loaded::Missing => self.use_credentials(self.credentials()),
+            Loaded::Plain(credentials) => self.use_credentials(credentials),
+            Loaded::Decrypted(credentials) => {
+                self.passphrase = passphrase.unwrap_or_default().to_owned();
+                self.use_credentials(credentials);
+            }
+            Loaded::Locked => {
+                self.credentials_locked = true;
+                self.use_credentials(self.credentials());
+            }
+        };
+        Ok(())
+    }
+
+    /// Takes the stored credentials, with the environment overrides on top.
+    fn use_credentials(&mut self, stored: Credentials) {
+        let mut credentials = stored.clone();
+        self.overridden = credentials::apply_env(&mut credentials);
+        self.stored = stored;
+        self.set_credentials(credentials);
+    }
+
+    #[cfg(test)]
+    fn use_overrides(&mut self, var: impl Fn(&str) -> Option<String>) {
+        let mut credentials = self.stored.clone();
+        self.overridden = credentials::apply_overrides(&mut credentials, var);
+        self.set_credentials(credentials);
+    }
+
+    /// Decrypts the credentials file of an already loaded config.
+    pub fn unlock(&mut self, passphrase: &str) -> Result<()> {
+        match credentials::load(&self.get_filepath()?, Some(passphrase))? {
+            Loaded::Decrypted(credentials) => {
+                self.use_credentials(credentials);
+                self.passphrase = passphrase.to_owned();
+                self.credentials_locked = false;
+                Ok(())
+            }
+            Loaded::Locked => Err(anyhow!("a passphrase is required")),
+            Loaded::Missing | Loaded::Plain(_) => {
+                self.credentials_locked = false;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn credentials(&self) -> Credentials {
+        Credentials {
+            user_access_token: self.user_access_token.clone(),
+            jwt: self.jwt.clone(),
+            nightbot_token: self.nightbot_token.clone(),
+            api_token: self.api_token.clone(),
+            webhook_secrets: self.webhook_secrets.clone(),
+        }
+    }
+
+    fn set_credentials(&mut self, credentials: Credentials) {
+        self.user_access_token = credentials.user_access_token;
+        self.jwt = credentials.jwt;
+        self.nightbot_token = credentials.nightbot_token;
+        self.api_token = credentials.api_token;
+        self.webhook_secrets = credentials.webhook_secrets;
     }
 
     pub fn write(&self) -> Result<()> {
-        let mut config_file = Config::get_filepath()?;
+        let mut config_file = self.get_filepath()?;
         DirBuilder::new()
             .recursive(true)
             .create(config_file.clone())?;
-        config_file.push("fishinge.conf");
+        if !self.credentials_locked {
+            let credentials =
+                credentials::to_store(&self.credentials(), &self.stored, &self.overridden);
+            credentials::write(&config_file, &credentials, &self.passphrase)?;
+        }
+        config_file.push(CONFIG_FILE);
+        if config_file.exists() && !migration::is_readable(&config_file) {
+            migration::backup(&config_file, "unreadable")?;
+        }
         let mut file_handle = OpenOptions::new()
             .write(true)
             .truncate(true)
@@ -73,31 +362,9 @@ impl Config {
         }
     }
 
-    pub fn test(&self) -> Result<()> {
-        let url_base = "https://api.streamelements.com/kappa/v2/";
-        let client = reqwest::blocking::Client::new();
-        let res: Vec<AccessResponse> = client
-            .get(url_base.to_string() + "users/access")
-            .header("Content-Type", "application/json")
-            .header("Accept", "application/json")
-            .header("Authorization", format!("Bearer {}", self.jwt()))
-            .send()
-            .context("Failed sending request to update list of users")?
-            .json::<Vec<AccessResponse>>()
-            .context("Failed to parse response for user list request")?;
-
-        let mut channel_id = String::new();
-        for res in res {
-            if res.username == self.streamer() {
-                channel_id = res.channelId;
-                break;
-            }
-        }
-
-        if channel_id.is_empty() {
-            return Err(anyhow!("channel_id not found"));
-        }
-
+    /// Connects to the chat bots the bindings use.
+    pub async fn test(&self) -> Result<()> {
+        Bots::connect(self).await?;
         Ok(())
     }
 
@@ -113,16 +380,76 @@ impl Config {
         &self.user_access_token
     }
 
-    pub fn reward_title(&self) -> &str {
-        &self.reward_title
-    }
-
     pub fn jwt(&self) -> &str {
         &self.jwt
     }
 
-    pub fn command_name(&self) -> &str {
-        &self.command_name
+    pub fn nightbot_token(&self) -> &str {
+        &self.nightbot_token
+    }
+
+    /// When the config or credentials file of the profile, or one of the
+    /// bindings' scripts, last changed.
+    pub fn modified(&self) -> Option<SystemTime> {
+        let dir = self.get_filepath().ok()?;
+        let scripts = self
+            .bindings
+            .iter()
+            .filter(|binding| !binding.script.is_empty())
+            .filter_map(|binding| script::path(&self.profile, &binding.script).ok());
+        [CONFIG_FILE, credentials::FILE_NAME]
+            .iter()
+            .map(|file| dir.join(file))
+            .chain(scripts)
+            .filter_map(|path| path.metadata().ok()?.modified().ok())
+            .max()
+    }
+
+    /// Whether switching to the other config needs new EventSub subscriptions,
+    /// rather than just taking effect on the next redemption.
+    pub fn needs_resubscribe(&self, other: &Config) -> bool {
+        self.client_id != other.client_id
+            || self.streamer != other.streamer
+            || self.user_access_token != other.user_access_token
+            || !self
+                .bindings
+                .iter()
+                .map(Binding::reward_title)
+                .eq(other.bindings.iter().map(Binding::reward_title))
+    }
+
+    /// Whether switching to the other config needs new chat bot clients.
+    pub fn needs_reconnect(&self, other: &Config) -> bool {
+        self.streamer != other.streamer
+            || self.jwt != other.jwt
+            || self.nightbot_token != other.nightbot_token
+            || [Bot::StreamElements, Bot::Nightbot]
+                .iter()
+                .any(|bot| self.uses(*bot) != other.uses(*bot))
+    }
+
+    /// Whether any action of any binding works on the bot. Scripts may
+    /// enable commands of their binding's bot.
+    pub fn uses(&self, bot: Bot) -> bool {
+        self.bindings.iter().any(|binding| {
+            (!binding.script.is_empty() && binding.bot == bot)
+                || binding
+                    .actions
+                    .iter()
+                    .any(|action| action.bot(binding.bot) == Some(bot))
+        })
+    }
+
+    /// Whether any binding refunds rejected redemptions.
+    pub fn refunds(&self) -> bool {
+        self.bindings.iter().any(|binding| binding.limits.refund)
+    }
+
+    /// The binding for the redeemed reward, matched by title like when subscribing.
+    pub fn binding_for(&self, event: &RedemptionEvent) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.reward_title() == event.reward.title)
     }
 }
 
@@ -181,16 +508,48 @@ struct RewardData {
     cooldown_expires_at: Option<String>,
 }
 
-pub fn write_output(output: &Arc<Mutex<String>>, text: &str) -> Result<()> {
-    match output.lock() {
-        Ok(mut out) => out.push_str(&format!("{}\n", text)),
-        _ => return Err(anyhow!("could not acquire clean lock on output")),
+/// Appends a line to the log. A thread that panicked while writing leaves
+/// at worst a partial line, so a poisoned lock is written to all the same.
+pub fn write_output(output: &Arc<Mutex<String>>, text: &str) {
+    let mut out = output.lock().unwrap_or_else(PoisonError::into_inner);
+    out.push_str(text);
+    out.push('\n');
+}
+
+/// Returns the broadcaster ID and the reward ID of every binding, in order.
+pub async fn get_ids(config: &Config) -> Result<(String, Vec<String>)> {
+    let client = reqwest::Client::new();
+    let broadcaster_id = get_broadcaster_id(&client, config).await?;
+    let mut reward_ids = Vec::new();
+    for binding in &config.bindings {
+        reward_ids.push(get_reward_id(&client, config, &broadcaster_id, binding).await?);
     }
-    Ok(())
+    Ok((broadcaster_id, reward_ids))
 }
 
-pub fn get_ids(config: &Config) -> Result<(String, String)> {
-    let client = reqwest::blocking::Client::new();
+async fn get_broadcaster_id(client: &reqwest::Client, config: &Config) -> Result<String> {
+    Ok(find_broadcaster(client, config).await?.id)
+}
+
+/// Whether the configured streamer is live right now.
+pub async fn is_live(config: &Config) -> Result<bool> {
+    Ok(live_since(config).await?.is_some())
+}
+
+/// When the configured streamer's stream started, if they are live.
+pub async fn live_since(config: &Config) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+    let broadcaster = find_broadcaster(&reqwest::Client::new(), config).await?;
+    if !broadcaster.is_live {
+        return Ok(None);
+    }
+    let started_at = broadcaster
+        .started_at
+        .parse()
+        .with_context(|| format!("Invalid stream start \"{}\"", broadcaster.started_at))?;
+    Ok(Some(started_at))
+}
+
+async fn find_broadcaster(client: &reqwest::Client, config: &Config) -> Result<BroadcasterData> {
     let res: BroadcasterResponse = client
         .get(format!(
             "https://api.twitch.tv/helix/search/channels?query={}",
@@ -201,7 +560,8 @@ pub fn get_ids(config: &Config) -> Result<(String, String)> {
             format!("Bearer {}", config.user_access_token()),
         )
         .header("Client-Id", config.client_id())
-        .send()
+        .send_tracked("helix")
+        .await
         .with_context(|| {
             format!(
                 "Failed sending request to get broadcaster ID of {}",
@@ -210,18 +570,24 @@ pub fn get_ids(config: &Config) -> Result<(String, String)> {
         })?
         .error_for_status()?
         .json::<BroadcasterResponse>()
+        .await
         .context("Failed to parse response for broadcaster ID request")?;
 
-    let mut broadcaster_id: String = String::new();
     for broadcaster in res.data {
         if broadcaster.broadcaster_login == config.streamer().to_lowercase() {
-            broadcaster_id = broadcaster.id;
-            break;
+            return Ok(broadcaster);
         }
     }
-    if broadcaster_id.is_empty() {
-        return Err(anyhow!("oh no, no id found"));
-    }
+
+    Err(anyhow!("oh no, no id found"))
+}
+
+async fn get_reward_id(
+    client: &reqwest::Client,
+    config: &Config,
+    broadcaster_id: &str,
+    binding: &Binding,
+) -> Result<String> {
     let res: RewardResponse = client
         .get(format!(
             "https://api.twitch.tv/helix/channel_points/custom_rewards?broadcaster_id={}",
@@ -232,29 +598,57 @@ pub fn get_ids(config: &Config) -> Result<(String, String)> {
             format!("Bearer {}", config.user_access_token()),
         )
         .header("Client-Id", config.client_id())
-        .send()
+        .send_tracked("helix")
+        .await
         .with_context(|| {
             format!(
                 "Failed sending request to get rewards of {}",
                 broadcaster_id,
             )
         })?
+        .error_for_status()?
         .json::<RewardResponse>()
+        .await
         .context("Failed to parse response for rewards list request")?;
 
-    let mut reward_id = String::new();
     for reward in res.data {
-        if reward.title == config.reward_title() {
-            reward_id = reward.id;
-            break;
+        if reward.title == binding.reward_title() {
+            return Ok(reward.id);
         }
     }
 
-    if reward_id.is_empty() {
-        return Err(anyhow!("reward not found"));
-    };
+    Err(anyhow!("reward \"{}\" not found", binding.reward_title()))
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize, Debug)]
+struct TokenInfo {
+    client_id: String,
+    login: String,
+    scopes: Vec<String>,
+    user_id: String,
+    expires_in: u64,
+}
 
-    Ok((broadcaster_id, reward_id))
+/// Also tells the metrics when the token expires.
+async fn validate_token(client: &reqwest::Client, config: &Config) -> Result<TokenInfo> {
+    let info = client
+        .get("https://id.twitch.tv/oauth2/validate")
+        .header(
+            "Authorization",
+            format!("OAuth {}", config.user_access_token()),
+        )
+        .send_tracked("twitch_auth")
+        .await
+        .context("Failed sending request to validate user access token")?
+        .error_for_status()
+        .context("User access token is invalid or expired")?
+        .json::<TokenInfo>()
+        .await
+        .context("Failed to parse response for token validation request")?;
+    let expires_in = chrono::Duration::seconds(info.expires_in as i64);
+    metrics::token_expires(&config.profile, chrono::Utc::now() + expires_in);
+    Ok(info)
 }
 
 #[derive(Serialize, Debug)]
@@ -266,7 +660,7 @@ struct RequestBody {
     transport: Transport,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 #[allow(dead_code)]
 struct RewardCondition {
     broadcaster_user_id: String,
@@ -319,38 +713,80 @@ struct Pagination {
     cursor: Option<String>,
 }
 
-pub fn is_subscribed(config: &Config, session_id: String) -> Result<bool> {
+pub async fn is_subscribed(config: &Config, session_id: String) -> Result<bool> {
     let mut subbed = false;
-    let response = reqwest::blocking::Client::new()
+    let response = reqwest::Client::new()
         .get("https://api.twitch.tv/helix/eventsub/subscriptions")
         .header(
             "Authorization",
             format!("Bearer {}", config.user_access_token()),
         )
         .header("Client-Id", config.client_id())
-        .send()
+        .send_tracked("helix")
+        .await
         .context("Failed sending request to get subscriptions")?
         .json::<SubscriptionsListResponse>()
+        .await
         .context("Failed to parse response for subscriptions list request")?;
 
     for subscription in response.data.iter() {
-        if subscription.status == "enabled".to_owned() {
-            if subscription.transport.session_id == session_id.to_owned() {
-                subbed = true;
-            }
+        if subscription.status == "enabled" && subscription.transport.session_id == session_id {
+            subbed = true;
         }
     }
 
     Ok(subbed)
 }
 
-pub fn create_subscription(
+/// A redemption subscription of an EventSub session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub id: String,
+    pub broadcaster_id: String,
+    pub reward_id: String,
+}
+
+/// The enabled redemption subscriptions of the session, e.g. the ones Twitch
+/// moved over to it after a reconnect.
+pub async fn session_subscriptions(config: &Config, session_id: &str) -> Result<Vec<Subscription>> {
+    let response = reqwest::Client::new()
+        .get("https://api.twitch.tv/helix/eventsub/subscriptions")
+        .query(&[("status", "enabled")])
+        .header(
+            "Authorization",
+            format!("Bearer {}", config.user_access_token()),
+        )
+        .header("Client-Id", config.client_id())
+        .send_tracked("helix")
+        .await
+        .context("Failed sending request to get subscriptions")?
+        .error_for_status()?
+        .json::<SubscriptionsListResponse>()
+        .await
+        .context("Failed to parse response for subscriptions list request")?;
+
+    Ok(response
+        .data
+        .into_iter()
+        .filter(|subscription| subscription.transport.session_id == session_id)
+        .filter_map(|subscription| {
+            let condition: RewardCondition = serde_json::from_value(subscription.condition).ok()?;
+            Some(Subscription {
+                id: subscription.id,
+                broadcaster_id: condition.broadcaster_user_id,
+                reward_id: condition.reward_id,
+            })
+        })
+        .collect())
+}
+
+pub async fn create_subscription(
     config: &Config,
     session_id: String,
     broadcaster_id: String,
     reward_id: String,
-) -> Result<()> {
-    let client = reqwest::blocking::Client::new();
+) -> Result<String> {
+    let client = reqwest::Client::new();
     let request_body = RequestBody {
         r#type: "channel.channel_points_custom_reward_redemption.add".into(),
         version: "1".into(),
@@ -375,7 +811,8 @@ pub fn create_subscription(
         .header("Client-Id", config.client_id())
         .header("Content-Type", "application/json")
         .json(&request_body)
-        .send()
+        .send_tracked("helix")
+        .await
         .with_context(|| {
             format!(
                 "Failed sending request to create subscription, with body: {:#?}",
@@ -383,136 +820,183 @@ pub fn create_subscription(
             )
         })?
         .json::<SubscriptionResponse>()
-        .context("Failed to parse response for subscription request")?;
+        .await
+        .context("Failed to parse response for subscription request")?
+        .data
+        .pop()
+        .map(|subscription| subscription.id)
+        .ok_or_else(|| anyhow!("Twitch did not return the created subscription"))
+}
 
+pub async fn delete_subscription(config: &Config, subscription_id: &str) -> Result<()> {
+    reqwest::Client::new()
+        .delete("https://api.twitch.tv/helix/eventsub/subscriptions")
+        .query(&[("id", subscription_id)])
+        .header(
+            "Authorization",
+            format!("Bearer {}", config.user_access_token()),
+        )
+        .header("Client-Id", config.client_id())
+        .send_tracked("helix")
+        .await
+        .context("Failed sending request to delete subscription")?
+        .error_for_status()
+        .with_context(|| format!("Could not delete subscription {}", subscription_id))?;
     Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-#[allow(dead_code, non_snake_case)]
-struct AccessResponse {
-    channelId: String,
-    username: String,
-    avatar: String,
-    provider: String,
-    role: String,
-}
-
-#[derive(Deserialize, Serialize, Debug, Default)]
-#[allow(dead_code, non_snake_case)]
-struct CommandResponse {
-    cooldown: Cooldown,
-    aliases: Vec<String>,
-    keywords: Vec<String>,
-    enabled: bool,
-    enabledOnline: bool,
-    enabledOffline: bool,
-    hidden: bool,
-    cost: i32,
-    r#type: String,
-    accessLevel: i32,
-    _id: String,
-    regex: Option<String>,
-    reply: String,
-    command: String,
-    channel: String,
-    createdAt: String,
-    updatedAt: String,
+#[derive(Serialize, Debug)]
+struct RedemptionStatusBody {
+    status: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Default)]
-#[allow(dead_code)]
-struct Cooldown {
-    user: i32,
-    global: i32,
+/// Cancels a redemption, which gives the viewer their points back. Only works
+/// for rewards created with the same client ID.
+pub async fn refund_redemption(config: &Config, event: &RedemptionEvent) -> Result<()> {
+    reqwest::Client::new()
+        .patch("https://api.twitch.tv/helix/channel_points/custom_rewards/redemptions")
+        .query(&[
+            ("id", event.id.as_str()),
+            ("broadcaster_id", event.broadcaster_user_id.as_str()),
+            ("reward_id", event.reward.id.as_str()),
+        ])
+        .header(
+            "Authorization",
+            format!("Bearer {}", config.user_access_token()),
+        )
+        .header("Client-Id", config.client_id())
+        .json(&RedemptionStatusBody {
+            status: "CANCELED".to_owned(),
+        })
+        .send_tracked("helix")
+        .await
+        .context("Failed sending request to refund redemption")?
+        .error_for_status()
+        .context("Twitch refused the refund, was the reward created with this client ID?")?;
+    Ok(())
 }
 
-pub fn update_command(output: &Arc<Mutex<String>>, config: &Config) -> Result<()> {
-    let url_base = "https://api.streamelements.com/kappa/v2/";
-    let client = reqwest::blocking::Client::new();
-    let res: Vec<AccessResponse> = client
-        .get(url_base.to_string() + "users/access")
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .header("Authorization", format!("Bearer {}", config.jwt()))
-        .send()
-        .context("Failed sending request to update list of users")?
-        .json::<Vec<AccessResponse>>()
-        .context("Failed to parse response for user list request")?;
-
-    let mut channel_id = String::new();
-    for res in res {
-        if res.username == config.streamer().to_lowercase() {
-            channel_id = res.channelId;
-            break;
-        }
-    }
-
-    if channel_id.is_empty() {
-        return Err(anyhow!("channel_id not found"));
-    };
-
-    let res: Vec<CommandResponse> = client
-        .get(url_base.to_string() + &format!("bot/commands/{}", channel_id))
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .header("Authorization", format!("Bearer {}", config.jwt()))
-        .send()
-        .context("Failed sending request to get command list")?
-        .json::<Vec<CommandResponse>>()
-        .context("Failed to parse response for command list request")?;
-
-    let mut command = CommandResponse::default();
-    for res in res {
-        if res.command == config.command_name() {
-            command = res;
-            break;
-        }
-    }
+#[derive(Serialize, Debug)]
+struct ChatMessageBody<'a> {
+    broadcaster_id: &'a str,
+    sender_id: &'a str,
+    message: &'a str,
+}
 
-    if command.command.is_empty() {
-        return Err(anyhow!("command \"{}\" not found", config.command_name()));
-    }
+#[derive(Deserialize, Debug)]
+struct ChatMessageResponse {
+    data: Vec<ChatMessageData>,
+}
 
-    command.enabledOnline = true;
+#[derive(Deserialize, Debug)]
+struct ChatMessageData {
+    is_sent: bool,
+    drop_reason: Option<DropReason>,
+}
 
-    let mut command = client
-        .put(url_base.to_string() + &format!("bot/commands/{}/{}", channel_id, command._id))
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .header("Authorization", format!("Bearer {}", config.jwt()))
-        .json(&command)
-        .send()
-        .context("Failed sending request to enable command")?
-        .json::<CommandResponse>()
-        .context("Failed to parse response for command enabling request")?;
+#[derive(Deserialize, Debug)]
+struct DropReason {
+    message: String,
+}
 
-    if command.command.is_empty() {
-        return Err(anyhow!("command not enabled correctly"));
+/// Sends a message to the broadcaster's chat as the broadcaster, which the
+/// token belongs to. Needs the `user:write:chat` scope.
+pub async fn send_chat_message(config: &Config, broadcaster_id: &str, message: &str) -> Result<()> {
+    let res = reqwest::Client::new()
+        .post("https://api.twitch.tv/helix/chat/messages")
+        .header(
+            "Authorization",
+            format!("Bearer {}", config.user_access_token()),
+        )
+        .header("Client-Id", config.client_id())
+        .json(&ChatMessageBody {
+            broadcaster_id,
+            sender_id: broadcaster_id,
+            message,
+        })
+        .send_tracked("helix")
+        .await
+        .context("Failed sending request to send chat message")?
+        .error_for_status()
+        .context("Twitch refused the chat message, does the token have the user:write:chat scope?")?
+        .json::<ChatMessageResponse>()
+        .await
+        .context("Failed to parse response for chat message request")?;
+    match res.data.into_iter().next() {
+        Some(data) if data.is_sent => Ok(()),
+        Some(ChatMessageData {
+            drop_reason: Some(reason),
+            ..
+        }) => Err(anyhow!("chat message was dropped: {}", reason.message)),
+        _ => Err(anyhow!("chat message was not sent")),
     }
+}
 
-    write_output(output, "Enabled command!")?;
-    write_output(output, "Waiting 5 minutes...")?;
+/// Formats whole minutes as such, anything else in seconds.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        60 => "1 minute".to_owned(),
+        secs if secs % 60 == 0 => format!("{} minutes", secs / 60),
+        1 => "1 second".to_owned(),
+        secs => format!("{} seconds", secs),
+    }
+}
 
-    sleep(Duration::from_secs(300));
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RedemptionEvent {
+    pub id: String,
+    pub broadcaster_user_id: String,
+    pub broadcaster_user_login: String,
+    pub broadcaster_user_name: String,
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+    #[serde(default)]
+    pub user_input: String,
+    pub status: String,
+    pub reward: RedemptionReward,
+    pub redeemed_at: String,
+}
 
-    command.enabledOnline = false;
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct RedemptionReward {
+    pub id: String,
+    pub title: String,
+    pub cost: u64,
+    #[serde(default)]
+    pub prompt: String,
+}
 
-    let command = client
-        .put(url_base.to_string() + &format!("bot/commands/{}/{}", channel_id, command._id))
-        .header("Content-Type", "application/json")
-        .header("Accept", "application/json")
-        .header("Authorization", format!("Bearer {}", config.jwt()))
-        .json(&command)
-        .send()
-        .context("Failed sending request to disable command")?
-        .json::<CommandResponse>()
-        .context("Failed to parse response for command disabling request")?;
+/// Reads the redemption out of an EventSub notification's `event` payload.
+pub fn parse_redemption<T: Serialize>(event: &T) -> Result<RedemptionEvent> {
+    let value = serde_json::to_value(event).context("Failed to serialize notification event")?;
+    serde_json::from_value(value).context("Notification is not a reward redemption")
+}
 
-    if command.command.is_empty() {
-        return Err(anyhow!("command not disabled correctly"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_overrides_are_not_written() {
+        let _root = testing::config_root();
+        let mut config = Config::new_profile("env-override").unwrap();
+        config.user_access_token = "stored token".to_owned();
+        config.jwt = "stored jwt".to_owned();
+        config.write().unwrap();
+
+        let mut config = Config::load_profile("env-override").unwrap();
+        config
+            .use_overrides(|name| (name == credentials::TOKEN_VAR).then(|| "env token".to_owned()));
+        assert_eq!(config.user_access_token(), "env token");
+        config.jwt = "new jwt".to_owned();
+        config.write().unwrap();
+
+        let dir = profile::dir("env-override").unwrap();
+        let written = read_to_string(dir.join(credentials::FILE_NAME)).unwrap();
+        assert!(!written.contains("env token"));
+        assert!(written.contains("stored token"));
+        assert!(written.contains("new jwt"));
     }
-
-    write_output(output, "Disabled command!")?;
-    Ok(())
 }
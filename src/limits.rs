@@ -0,0 +1,208 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::history::Entry;
+use crate::stats::STREAM_GAP_HOURS;
+use crate::RedemptionEvent;
+
+/// Longest cooldown that can be configured, which is also how far back
+/// accepted redemptions are remembered besides the current stream.
+pub const MAX_COOLDOWN_SECS: u64 = 24 * 60 * 60;
+
+/// Extra rules for a binding on top of Twitch's own reward limits. Zero
+/// turns a rule off.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// How long a viewer has to wait between their own redemptions.
+    #[serde(default)]
+    pub user_cooldown_secs: u64,
+    /// How long anyone has to wait after any accepted redemption.
+    #[serde(default)]
+    pub global_cooldown_secs: u64,
+    #[serde(default)]
+    pub max_per_user_per_stream: u32,
+    /// Whether rejected redemptions give the viewer their points back.
+    #[serde(default)]
+    pub refund: bool,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.user_cooldown_secs == 0
+            && self.global_cooldown_secs == 0
+            && self.max_per_user_per_stream == 0
+    }
+
+    /// Checks a redemption against the accepted redemptions of the same
+    /// binding, oldest first, returning why it breaks a rule. `live_since`
+    /// is when the stream started, see [`current_stream`].
+    pub fn check(
+        &self,
+        event: &RedemptionEvent,
+        accepted: &[Entry],
+        now: DateTime<Utc>,
+        live_since: Option<DateTime<Utc>>,
+    ) -> Result<(), String> {
+        if let Some(last) = accepted.last() {
+            let remaining = remaining(last.redeemed_at, self.global_cooldown_secs, now);
+            if remaining > Duration::zero() {
+                return Err(format!(
+                    "global cooldown, {} left",
+                    format_remaining(remaining)
+                ));
+            }
+        }
+
+        if let Some(last) = accepted
+            .iter()
+            .rev()
+            .find(|entry| entry.user_id == event.user_id)
+        {
+            let remaining = remaining(last.redeemed_at, self.user_cooldown_secs, now);
+            if remaining > Duration::zero() {
+                return Err(format!(
+                    "{} is on cooldown, {} left",
+                    event.user_name,
+                    format_remaining(remaining)
+                ));
+            }
+        }
+        let by_user = current_stream(accepted, now, live_since)
+            .iter()
+            .filter(|entry| entry.user_id == event.user_id)
+            .count();
+        if self.max_per_user_per_stream != 0 && by_user >= self.max_per_user_per_stream as usize {
+            return Err(format!(
+                "{} reached the limit of {} per stream",
+                event.user_name, self.max_per_user_per_stream
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn remaining(since: DateTime<Utc>, cooldown_secs: u64, now: DateTime<Utc>) -> Duration {
+    since + Duration::seconds(cooldown_secs as i64) - now
+}
+
+fn format_remaining(remaining: Duration) -> String {
+    crate::format_duration(std::time::Duration::from_secs(
+        remaining.num_seconds().max(1) as u64,
+    ))
+}
+
+/// The entries of the stream still going on: those since it started, as
+/// Twitch reports it. While offline, or if that could not be looked up, by
+/// the same gap rule as the stats.
+pub fn current_stream(
+    entries: &[Entry],
+    now: DateTime<Utc>,
+    live_since: Option<DateTime<Utc>>,
+) -> &[Entry] {
+    if let Some(started_at) = live_since {
+        let start = entries.partition_point(|entry| entry.redeemed_at < started_at);
+        return &entries[start..];
+    }
+    let gap = Duration::hours(STREAM_GAP_HOURS);
+    let mut start = entries.len();
+    let mut next = now;
+    while start > 0 && next - entries[start - 1].redeemed_at <= gap {
+        start -= 1;
+        next = entries[start].redeemed_at;
+    }
+    &entries[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Outcome;
+
+    fn event(user: &str, redeemed_at: &str) -> RedemptionEvent {
+        RedemptionEvent {
+            user_id: user.to_owned(),
+            user_name: user.to_owned(),
+            redeemed_at: redeemed_at.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn accepted(user: &str, redeemed_at: &str) -> Entry {
+        Entry::new(
+            &event(user, redeemed_at),
+            Outcome::Enabled,
+            std::time::Duration::ZERO,
+        )
+    }
+
+    fn now(time: &str) -> DateTime<Utc> {
+        format!("2024-01-01T{}Z", time).parse().unwrap()
+    }
+
+    #[test]
+    fn cooldowns() {
+        let limits = Limits {
+            user_cooldown_secs: 600,
+            global_cooldown_secs: 60,
+            ..Default::default()
+        };
+        let accepted = [accepted("ann", "2024-01-01T12:00:00Z")];
+        let ann = event("ann", "");
+        let bob = event("bob", "");
+        assert_eq!(
+            limits.check(&bob, &accepted, now("12:00:30"), None),
+            Err("global cooldown, 30 seconds left".to_owned())
+        );
+        assert_eq!(limits.check(&bob, &accepted, now("12:01:00"), None), Ok(()));
+        assert!(limits
+            .check(&ann, &accepted, now("12:05:00"), None)
+            .is_err());
+        assert_eq!(limits.check(&ann, &accepted, now("12:10:00"), None), Ok(()));
+    }
+
+    #[test]
+    fn max_per_stream_resets_after_a_gap() {
+        let limits = Limits {
+            max_per_user_per_stream: 2,
+            ..Default::default()
+        };
+        let accepted = [
+            accepted("ann", "2024-01-01T08:00:00Z"),
+            accepted("ann", "2024-01-01T13:00:00Z"),
+            accepted("ann", "2024-01-01T14:00:00Z"),
+        ];
+        let ann = event("ann", "");
+        assert!(limits
+            .check(&ann, &accepted, now("15:00:00"), None)
+            .is_err());
+        assert_eq!(
+            limits.check(&ann, &accepted[..2], now("15:00:00"), None),
+            Ok(())
+        );
+        assert_eq!(
+            limits.check(&event("bob", ""), &accepted, now("15:00:00"), None),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn max_per_stream_counts_since_the_stream_started() {
+        let limits = Limits {
+            max_per_user_per_stream: 2,
+            ..Default::default()
+        };
+        let accepted = [
+            accepted("ann", "2024-01-01T12:00:00Z"),
+            accepted("ann", "2024-01-01T13:00:00Z"),
+            accepted("ann", "2024-01-01T14:00:00Z"),
+        ];
+        let ann = event("ann", "");
+        assert!(limits
+            .check(&ann, &accepted, now("15:00:00"), Some(now("12:30:00")))
+            .is_err());
+        assert_eq!(
+            limits.check(&ann, &accepted, now("15:00:00"), Some(now("13:30:00"))),
+            Ok(())
+        );
+    }
+}
@@ -1,212 +1,1758 @@
-#![allow(clippy::expect_used, clippy::unwrap_used)]
+use anyhow::{anyhow, Result};
+use chrono::{Utc, Weekday};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::task::JoinHandle;
 
-use anyhow::Result;
-use eventsub_websocket::types::TwitchMessage;
-use eventsub_websocket::{event_handler, get_default_url, CloseCode, CloseFrame};
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread::{self, JoinHandle};
+use fishinge::action::ActionConfig;
+use fishinge::announce::{Announcement, Via};
+use fishinge::chatbot::Bot;
+use fishinge::error::{self, Error};
+use fishinge::gating::{Fallback, Gate, ScheduleWindow};
+use fishinge::history::{self, Entry};
+use fishinge::limits::{Limits, MAX_COOLDOWN_SECS};
+use fishinge::program::{Invocation, Program};
+use fishinge::runtime::{Channel, Control, Status as ChannelStatus};
+use fishinge::stats::{Period, Report, Summary, STREAM_GAP_HOURS};
+use fishinge::streamelements::Reply;
+use fishinge::validation::{self, Status, Step};
+use fishinge::webhook::Webhook;
+use fishinge::{credentials, profile};
+use fishinge::{
+    format_duration, list_commands, write_output, Binding, CommandRef, CommandResponse, Config,
+};
 
-use fishinge::{create_subscription, get_ids, is_subscribed, write_output, Config};
-use fishinge::{if_err_writer, let_match_writer, write_expect};
+/// What the setup view asks to start.
+struct Launch {
+    config: Config,
+    /// Other profiles to run alongside, loaded from disk.
+    also_run: Vec<String>,
+}
 
-struct FishingeSetup {
+/// The editable settings of a profile, shared by the setup view and the
+/// settings view of a channel.
+struct SettingsForm {
     config: Config,
+    commands: Vec<CommandResponse>,
+    commands_request: Option<oneshot::Receiver<Result<Vec<CommandResponse>>>>,
+    commands_error: Option<String>,
+    unlock_passphrase: String,
+    unlock_error: Option<String>,
+    /// The config with its credentials decrypted, see [`spawn_blocking`].
+    unlock_request: Option<oneshot::Receiver<Result<Config>>>,
+    save_request: Option<oneshot::Receiver<Result<()>>>,
 }
 
-impl eframe::App for FishingeSetup {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Client ID");
-            ui.text_edit_singleline(&mut self.config.client_id);
-            ui.heading("Streamer");
-            ui.text_edit_singleline(&mut self.config.streamer);
-            ui.heading("User Access Token");
-            ui.text_edit_singleline(&mut self.config.user_access_token);
-            ui.heading("Reward Title");
-            ui.text_edit_singleline(&mut self.config.reward_title);
-            ui.heading("JWT");
-            ui.text_edit_singleline(&mut self.config.jwt);
-            ui.heading("Command Name");
-            ui.text_edit_singleline(&mut self.config.command_name);
-            ui.with_layout(egui::Layout::left_to_right(egui::Align::BOTTOM), |ui| {
-                if ui.button("Launch").clicked() {
-                    let config = &self.config;
-                    config.write().unwrap();
-                    frame.close();
+impl SettingsForm {
+    fn new(config: Config) -> SettingsForm {
+        SettingsForm {
+            config,
+            commands: Vec::new(),
+            commands_request: None,
+            commands_error: None,
+            unlock_passphrase: String::new(),
+            unlock_error: None,
+            unlock_request: None,
+            save_request: None,
+        }
+    }
+
+    fn fields(&mut self, ui: &mut egui::Ui) {
+        self.poll_unlock(ui.ctx());
+        // The unlocked config replaces this one, so nothing is edited meanwhile.
+        ui.set_enabled(self.unlock_request.is_none());
+        ui.heading("Client ID");
+        ui.text_edit_singleline(&mut self.config.client_id);
+        ui.heading("Streamer");
+        ui.text_edit_singleline(&mut self.config.streamer);
+        self.credentials(ui);
+        ui.heading("Overlay");
+        ui.horizontal(|ui| {
+            ui.label("Port");
+            ui.add(egui::DragValue::new(&mut self.config.overlay_port));
+            if self.config.overlay_port == 0 {
+                ui.label("off");
+            } else {
+                ui.label(format!(
+                    "browser source http://127.0.0.1:{}/",
+                    self.config.overlay_port
+                ));
+            }
+        });
+        ui.heading("Control API");
+        ui.horizontal(|ui| {
+            ui.label("Port");
+            ui.add(egui::DragValue::new(&mut self.config.api_port));
+            if self.config.api_port == 0 {
+                ui.label("off");
+            }
+        });
+        if self.config.api_port != 0 && !self.config.credentials_locked {
+            ui.label("Token");
+            secret_field(ui, &mut self.config.api_token);
+        }
+        if !self.config.credentials_locked {
+            self.webhook_secrets(ui);
+        }
+        self.bindings(ui);
+    }
+
+    /// Secrets webhooks sign with or send as headers, kept in the
+    /// credentials file like the other secrets.
+    fn webhook_secrets(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Webhook secrets");
+        let mut secrets: Vec<(String, String)> =
+            self.config.webhook_secrets.clone().into_iter().collect();
+        let mut removed = None;
+        for (index, (name, value)) in secrets.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(name).desired_width(120.));
+                ui.add(
+                    egui::TextEdit::singleline(value)
+                        .password(true)
+                        .desired_width(160.),
+                );
+                if ui.small_button("Remove").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            secrets.remove(index);
+        }
+        if ui.button("Add webhook secret").clicked() {
+            secrets.push((format!("secret{}", secrets.len() + 1), String::new()));
+        }
+        self.config.webhook_secrets = secrets.into_iter().collect();
+    }
+
+    fn credentials(&mut self, ui: &mut egui::Ui) {
+        if self.config.credentials_locked {
+            ui.heading("Passphrase");
+            ui.label("The saved credentials are encrypted.");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.unlock_passphrase).password(true));
+                if ui.button("Unlock").clicked() {
+                    let mut config = self.config.clone();
+                    let passphrase = self.unlock_passphrase.clone();
+                    self.unlock_error = None;
+                    self.unlock_request = Some(spawn_blocking(move || {
+                        config.unlock(&passphrase).map(|_| config)
+                    }));
+                }
+                if self.unlock_request.is_some() {
+                    ui.spinner();
                 }
             });
+            if let Some(err) = &self.unlock_error {
+                ui.colored_label(egui::Color32::RED, err);
+            }
+            return;
+        }
+        ui.heading("User Access Token");
+        secret_field(ui, &mut self.config.user_access_token);
+        ui.heading("JWT");
+        secret_field(ui, &mut self.config.jwt);
+        if self.config.uses(Bot::Nightbot) || !self.config.nightbot_token.is_empty() {
+            ui.heading("Nightbot Token");
+            secret_field(ui, &mut self.config.nightbot_token);
+        }
+        ui.heading("Passphrase (optional)");
+        secret_field(ui, &mut self.config.passphrase);
+    }
+
+    fn poll_unlock(&mut self, ctx: &egui::Context) {
+        match poll_blocking(&mut self.unlock_request, ctx) {
+            Some(Ok(config)) => self.config = config,
+            Some(Err(err)) => self.unlock_error = Some(format!("{:#}", err)),
+            None => {}
+        }
+    }
+
+    fn fetch_commands(&mut self) {
+        let config = self.config.clone();
+        self.commands_error = None;
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = tx.send(list_commands(&config).await);
+        });
+        self.commands_request = Some(rx);
+    }
+
+    fn poll_commands(&mut self, ctx: &egui::Context) {
+        let Some(mut request) = self.commands_request.take() else {
+            return;
+        };
+        match request.try_recv() {
+            Err(TryRecvError::Empty) => {
+                self.commands_request = Some(request);
+                ctx.request_repaint();
+            }
+            Ok(Ok(commands)) => self.commands = commands,
+            Ok(Err(err)) => self.commands_error = Some(format!("{:#}", err)),
+            Err(TryRecvError::Closed) => {
+                self.commands_error = Some("command request panicked".to_owned())
+            }
+        }
+    }
+
+    fn bindings(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Bindings");
+        ui.horizontal(|ui| {
+            let fetching = self.commands_request.is_some();
+            if ui
+                .add_enabled(!fetching, egui::Button::new("Fetch commands"))
+                .clicked()
+            {
+                self.fetch_commands();
+            }
+            if fetching {
+                ui.spinner();
+            }
         });
+        if let Some(err) = &self.commands_error {
+            ui.colored_label(egui::Color32::RED, err);
+        }
+
+        let mut removed = None;
+        for (index, binding) in self.config.bindings.iter_mut().enumerate() {
+            egui::CollapsingHeader::new(format!(
+                "{} → {}",
+                binding.reward_title,
+                binding.describe()
+            ))
+            .id_source(("binding", index))
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.label("Reward Title");
+                ui.text_edit_singleline(&mut binding.reward_title);
+                ui.horizontal(|ui| {
+                    ui.label("Chat bot");
+                    egui::ComboBox::from_id_source(("bot", index))
+                        .selected_text(binding.bot.to_string())
+                        .show_ui(ui, |ui| {
+                            for bot in [Bot::StreamElements, Bot::Nightbot] {
+                                ui.selectable_value(&mut binding.bot, bot, bot.to_string());
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Duration");
+                    ui.add(
+                        egui::DragValue::new(&mut binding.duration_secs)
+                            .clamp_range(1..=24 * 60 * 60)
+                            .suffix(" s"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Script");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut binding.script)
+                            .hint_text("none, e.g. pond.rhai"),
+                    );
+                });
+                limits_editor(ui, index, &mut binding.limits);
+                gate_editor(ui, index, &mut binding.gate);
+                actions_editor(ui, index, binding, &self.commands);
+                if ui.button("Remove binding").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            self.config.bindings.remove(index);
+        }
+        if ui.button("Add binding").clicked() {
+            self.config.bindings.push(Binding::default());
+        }
+
+        if !self.commands.is_empty() {
+            command_list(ui, &self.commands);
+        }
     }
 }
 
-struct FishingeOutput {
-    output: Arc<Mutex<String>>,
-    handle: Option<JoinHandle<Result<(), anyhow::Error>>>,
+struct FishingeSetup {
+    form: SettingsForm,
+    validation: Arc<Mutex<Vec<Step>>>,
+    validation_request: Option<JoinHandle<()>>,
+    validated_config: Option<Config>,
+    load_error: Option<String>,
+    profiles: Vec<String>,
+    new_profile: String,
+    profile_error: Option<String>,
+    /// A profile being switched to or copied, with its load error.
+    profile_request: Option<oneshot::Receiver<Result<LoadedConfig>>>,
+    /// What to start once the config is saved.
+    saving: Option<(Launch, oneshot::Receiver<Result<()>>)>,
+    also_run: Vec<String>,
 }
 
-impl eframe::App for FishingeOutput {
-    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        ctx.request_repaint();
-        egui::CentralPanel::default().show(ctx, |ui| -> Result<()> {
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                let text = self.output.lock().unwrap().clone();
-                ui.horizontal_wrapped(|ui| {
-                    ui.monospace(text);
+impl FishingeSetup {
+    fn new(config: Config, load_error: Option<String>, also_run: Vec<String>) -> FishingeSetup {
+        let mut setup = FishingeSetup {
+            validation: Arc::new(Mutex::new(validation::steps(&config))),
+            form: SettingsForm::new(config),
+            validation_request: None,
+            validated_config: None,
+            load_error,
+            profiles: Vec::new(),
+            new_profile: String::new(),
+            profile_error: None,
+            profile_request: None,
+            saving: None,
+            also_run,
+        };
+        setup.refresh_profiles();
+        setup
+    }
+
+    fn refresh_profiles(&mut self) {
+        self.profiles = profile::list().unwrap_or_else(|_| vec![profile::DEFAULT.to_owned()]);
+        if !self.profiles.contains(&self.form.config.profile) {
+            self.profiles.push(self.form.config.profile.clone());
+        }
+    }
+
+    fn set_config(&mut self, config: Config, load_error: Option<String>) {
+        self.validation = Arc::new(Mutex::new(validation::steps(&config)));
+        self.validated_config = None;
+        self.form = SettingsForm::new(config);
+        self.load_error = load_error;
+        self.refresh_profiles();
+    }
+
+    fn switch_profile(&mut self, name: &str) {
+        let name = name.to_owned();
+        self.profile_request = Some(spawn_blocking(move || load_config(&name)));
+    }
+
+    fn poll_profile(&mut self, ctx: &egui::Context) {
+        match poll_blocking(&mut self.profile_request, ctx) {
+            Some(Ok((config, load_error))) => self.set_config(config, load_error),
+            Some(Err(err)) => self.profile_error = Some(format!("{:#}", err)),
+            None => {}
+        }
+    }
+
+    fn profiles(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Profile");
+        let mut selected = None;
+        egui::ComboBox::from_id_source("profile")
+            .selected_text(&self.form.config.profile)
+            .show_ui(ui, |ui| {
+                for profile in &self.profiles {
+                    if ui
+                        .selectable_label(*profile == self.form.config.profile, profile)
+                        .clicked()
+                    {
+                        selected = Some(profile.clone());
+                    }
+                }
+            });
+        if let Some(profile) = selected {
+            if profile != self.form.config.profile {
+                self.profile_error = None;
+                self.switch_profile(&profile);
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_profile)
+                    .hint_text("New profile")
+                    .desired_width(120.),
+            );
+            if ui.button("Create").clicked() {
+                match Config::new_profile(&self.new_profile) {
+                    Ok(_) if self.profiles.contains(&self.new_profile) => {
+                        self.profile_error =
+                            Some(format!("profile \"{}\" already exists", self.new_profile));
+                    }
+                    Ok(config) => {
+                        self.profile_error = None;
+                        self.set_config(config, None);
+                    }
+                    Err(err) => self.profile_error = Some(format!("{:#}", err)),
+                }
+            }
+            if ui.button("Copy current").clicked() {
+                // Keep an already unlocked copy unlocked.
+                let passphrase = Some(self.form.config.passphrase.clone())
+                    .filter(|passphrase| !passphrase.is_empty())
+                    .or_else(credentials::env_passphrase);
+                let (from, to) = (self.form.config.profile.clone(), self.new_profile.clone());
+                self.profile_error = None;
+                self.profile_request = Some(spawn_blocking(move || {
+                    profile::copy_profile(&from, &to)?;
+                    Ok((
+                        Config::load_with_passphrase(&to, passphrase.as_deref())?,
+                        None,
+                    ))
+                }));
+            }
+            if self.profile_request.is_some() {
+                ui.spinner();
+            }
+        });
+        if let Some(err) = &self.profile_error {
+            ui.colored_label(egui::Color32::RED, err);
+        }
+        self.also_run(ui);
+    }
+
+    /// Other saved profiles can be listened to at the same time, each in its
+    /// own tab of the dashboard.
+    fn also_run(&mut self, ui: &mut egui::Ui) {
+        let others: Vec<&String> = self
+            .profiles
+            .iter()
+            .filter(|profile| **profile != self.form.config.profile)
+            .collect();
+        if others.is_empty() {
+            return;
+        }
+        ui.label("Also run");
+        ui.horizontal_wrapped(|ui| {
+            for profile in others {
+                let mut checked = self.also_run.contains(profile);
+                if ui.checkbox(&mut checked, profile).changed() {
+                    if checked {
+                        self.also_run.push(profile.clone());
+                    } else {
+                        self.also_run.retain(|other| other != profile);
+                    }
+                }
+            }
+        });
+    }
+
+    fn validate(&mut self) {
+        let config = self.form.config.clone();
+        let steps = Arc::clone(&self.validation);
+        *steps.lock().unwrap_or_else(PoisonError::into_inner) = validation::steps(&config);
+        self.validated_config = Some(config.clone());
+        self.validation_request = Some(tokio::spawn(async move {
+            validation::run(&config, &steps).await
+        }));
+    }
+
+    fn poll_validation(&mut self, ctx: &egui::Context) {
+        if let Some(handle) = self.validation_request.take() {
+            if !handle.is_finished() {
+                self.validation_request = Some(handle);
+            }
+            ctx.request_repaint();
+        }
+    }
+
+    /// Launching is only allowed once the current config has been validated
+    /// without any hard failures.
+    fn can_launch(&self) -> bool {
+        self.validation_request.is_none()
+            && self.saving.is_none()
+            && self.validated_config.as_ref() == Some(&self.form.config)
+            && validation::passed(
+                &self
+                    .validation
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner),
+            )
+    }
+
+    fn validation_view(&mut self, ui: &mut egui::Ui) {
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.heading("Validation");
+            if ui
+                .add_enabled(
+                    self.validation_request.is_none(),
+                    egui::Button::new("Validate"),
+                )
+                .clicked()
+            {
+                self.validate();
+            }
+        });
+        if self.validated_config.is_none() {
+            ui.label("Validate the settings before launching.");
+            return;
+        }
+        if self.validated_config.as_ref() != Some(&self.form.config) {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                "Settings changed since the last validation.",
+            );
+        }
+        for step in self
+            .validation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+        {
+            ui.horizontal(|ui| {
+                match &step.status {
+                    Status::Pending => ui.label("·"),
+                    Status::Running => ui.spinner(),
+                    Status::Passed(_) => ui.colored_label(egui::Color32::GREEN, "✔"),
+                    Status::Warning(_) => ui.colored_label(egui::Color32::YELLOW, "⚠"),
+                    Status::Failed(_) => ui.colored_label(egui::Color32::RED, "✖"),
+                    Status::Skipped(_) => ui.colored_label(egui::Color32::GRAY, "–"),
+                };
+                ui.label(&step.name);
+            });
+            match &step.status {
+                Status::Passed(message)
+                | Status::Warning(message)
+                | Status::Failed(message)
+                | Status::Skipped(message) => {
+                    ui.indent(&step.name, |ui| ui.small(message));
+                }
+                Status::Pending | Status::Running => {}
+            }
+        }
+    }
+}
+
+fn limits_editor(ui: &mut egui::Ui, index: usize, limits: &mut Limits) {
+    egui::CollapsingHeader::new(if limits.is_empty() {
+        "Limits (none)"
+    } else {
+        "Limits"
+    })
+    .id_source(("limits", index))
+    .show(ui, |ui| {
+        egui::Grid::new(("limits_grid", index)).show(ui, |ui| {
+            ui.label("Cooldown per viewer");
+            ui.add(
+                egui::DragValue::new(&mut limits.user_cooldown_secs)
+                    .clamp_range(0..=MAX_COOLDOWN_SECS)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+            ui.label("Cooldown for everyone");
+            ui.add(
+                egui::DragValue::new(&mut limits.global_cooldown_secs)
+                    .clamp_range(0..=MAX_COOLDOWN_SECS)
+                    .suffix(" s"),
+            );
+            ui.end_row();
+            ui.label("Per viewer and stream").on_hover_text(format!(
+                "Counted since the stream started on Twitch. While offline, redemptions more than {} hours apart count as separate streams.",
+                STREAM_GAP_HOURS
+            ));
+            ui.add(
+                egui::DragValue::new(&mut limits.max_per_user_per_stream)
+                    .clamp_range(0..=1000)
+                    .suffix("×"),
+            );
+            ui.end_row();
+        });
+        ui.small("0 turns a limit off.");
+        ui.checkbox(&mut limits.refund, "Refund rejected redemptions");
+    });
+}
+
+fn gate_editor(ui: &mut egui::Ui, index: usize, gate: &mut Gate) {
+    egui::CollapsingHeader::new(if gate.is_always_open() {
+        "When (always)"
+    } else {
+        "When"
+    })
+    .id_source(("gate", index))
+    .show(ui, |ui| {
+        ui.checkbox(&mut gate.require_live, "Only while live");
+        let mut removed = None;
+        for (window_index, window) in gate.schedule.iter_mut().enumerate() {
+            ui.horizontal_wrapped(|ui| {
+                for day in [
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                    Weekday::Sat,
+                    Weekday::Sun,
+                ] {
+                    let mut on = window.days.contains(&day);
+                    if ui.checkbox(&mut on, day.to_string()).changed() {
+                        if on {
+                            window.days.push(day);
+                            window.days.sort_by_key(Weekday::num_days_from_monday);
+                        } else {
+                            window.days.retain(|other| *other != day);
+                        }
+                    }
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut window.start).desired_width(40.));
+                ui.label("to");
+                ui.add(egui::TextEdit::singleline(&mut window.end).desired_width(40.));
+                if ui.button("Remove").clicked() {
+                    removed = Some(window_index);
+                }
+            });
+        }
+        if let Some(window_index) = removed {
+            gate.schedule.remove(window_index);
+        }
+        if ui.button("Add time window").clicked() {
+            gate.schedule.push(ScheduleWindow::default());
+        }
+        if !gate.schedule.is_empty() {
+            ui.small("Local time. No day ticked means every day.");
+        }
+        egui::ComboBox::from_id_source(("fallback", index))
+            .selected_text(match gate.otherwise {
+                Fallback::Defer => "Otherwise defer",
+                Fallback::Refund => "Otherwise refund",
+                Fallback::Reject => "Otherwise reject",
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut gate.otherwise, Fallback::Defer, "Otherwise defer");
+                ui.selectable_value(&mut gate.otherwise, Fallback::Refund, "Otherwise refund");
+                ui.selectable_value(&mut gate.otherwise, Fallback::Reject, "Otherwise reject");
+            });
+    });
+}
+
+fn actions_editor(
+    ui: &mut egui::Ui,
+    index: usize,
+    binding: &mut Binding,
+    commands: &[CommandResponse],
+) {
+    let bot = binding.bot;
+    let actions = &mut binding.actions;
+    egui::CollapsingHeader::new(format!("Actions ({})", actions.len()))
+        .id_source(("actions", index))
+        .default_open(true)
+        .show(ui, |ui| {
+            let mut removed = None;
+            let mut raised = None;
+            for (action_index, action) in actions.iter_mut().enumerate() {
+                let id = (index, action_index);
+                ui.horizontal(|ui| {
+                    ui.strong(format!("{}. {}", action_index + 1, action_kind(action)));
+                    if action_index > 0 && ui.small_button("⏶").clicked() {
+                        raised = Some(action_index);
+                    }
+                    if ui.small_button("Remove").clicked() {
+                        removed = Some(action_index);
+                    }
                 });
+                // Only StreamElements commands are fetched for picking.
+                let commands = match action.bot(bot) {
+                    Some(Bot::StreamElements) => commands,
+                    _ => &[],
+                };
+                ui.indent(id, |ui| action_editor(ui, id, action, commands));
+            }
+            if let Some(action_index) = raised {
+                actions.swap(action_index - 1, action_index);
+            }
+            if let Some(action_index) = removed {
+                actions.remove(action_index);
+            }
+            ui.menu_button("Add action", |ui| {
+                for action in [
+                    ActionConfig::ToggleCommand(CommandRef::default()),
+                    ActionConfig::StreamElementsReply(Reply {
+                        template: "{user} opened the pond: {input}".to_owned(),
+                        ..Reply::default()
+                    }),
+                    ActionConfig::Announce(Announcement::default()),
+                    ActionConfig::Webhook(Webhook::default()),
+                    ActionConfig::RunProgram(Program::default()),
+                ] {
+                    if ui.button(action_kind(&action)).clicked() {
+                        actions.push(action);
+                        ui.close_menu();
+                    }
+                }
+            });
+            ui.small("Started in order when the binding opens, stopped in reverse when it closes.");
+        });
+}
+
+const PLACEHOLDERS: &str =
+    "{user}, {login}, {input}, {reward}, {cost} and {remaining} are filled in.";
+
+fn action_kind(action: &ActionConfig) -> &'static str {
+    match action {
+        ActionConfig::ToggleCommand(_) => "Enable command",
+        ActionConfig::StreamElementsReply(_) => "Change StreamElements reply",
+        ActionConfig::Announce(_) => "Announce in chat",
+        ActionConfig::Webhook(_) => "Send webhook",
+        ActionConfig::RunProgram(_) => "Run program",
+    }
+}
+
+fn action_editor(
+    ui: &mut egui::Ui,
+    id: (usize, usize),
+    action: &mut ActionConfig,
+    commands: &[CommandResponse],
+) {
+    match action {
+        ActionConfig::ToggleCommand(command) => {
+            ui.horizontal(|ui| command_picker(ui, id, command, commands));
+        }
+        ActionConfig::StreamElementsReply(reply) => {
+            ui.horizontal(|ui| command_picker(ui, id, &mut reply.command, commands));
+            ui.label("Reply");
+            ui.text_edit_singleline(&mut reply.template);
+            ui.small(PLACEHOLDERS);
+            optional_value(
+                ui,
+                "Cooldown per viewer",
+                &mut reply.user_cooldown_secs,
+                " s",
+            );
+            optional_value(
+                ui,
+                "Cooldown for everyone",
+                &mut reply.global_cooldown_secs,
+                " s",
+            );
+            optional_value(ui, "Cost", &mut reply.cost, " points");
+        }
+        ActionConfig::Announce(announcement) => {
+            egui::Grid::new(("announce", id)).show(ui, |ui| {
+                ui.label("When opened");
+                ui.text_edit_singleline(&mut announcement.on_enable);
+                ui.end_row();
+                ui.add(
+                    egui::DragValue::new(&mut announcement.reminder_secs)
+                        .clamp_range(0..=24 * 60 * 60)
+                        .prefix("Before closing ")
+                        .suffix(" s"),
+                );
+                ui.text_edit_singleline(&mut announcement.on_reminder);
+                ui.end_row();
+                ui.label("When closed");
+                ui.text_edit_singleline(&mut announcement.on_disable);
+                ui.end_row();
+            });
+            ui.small(format!("{} Empty messages are not sent.", PLACEHOLDERS));
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut announcement.via, Via::Helix, "Chat API");
+                ui.radio_value(&mut announcement.via, Via::Irc, "IRC");
+                if announcement.via == Via::Irc {
+                    ui.text_edit_singleline(&mut announcement.irc_endpoint);
+                }
             });
+        }
+        ActionConfig::Webhook(webhook) => webhook_editor(ui, id, webhook),
+        ActionConfig::RunProgram(program) => program_editor(ui, id, program),
+    }
+}
+
+fn program_editor(ui: &mut egui::Ui, id: (usize, usize), program: &mut Program) {
+    invocation_editor(ui, ("program_start", id), &mut program.start);
+    let mut end = program.end.is_some();
+    if ui
+        .checkbox(&mut end, "Run another program when closed")
+        .changed()
+    {
+        program.end = end.then(Invocation::default);
+    }
+    if let Some(end) = &mut program.end {
+        invocation_editor(ui, ("program_end", id), end);
+    }
+    ui.small(format!(
+        "One argument per line, no shell involved. {}",
+        PLACEHOLDERS
+    ));
+    ui.horizontal(|ui| {
+        ui.label("Killed after");
+        ui.add(
+            egui::DragValue::new(&mut program.timeout_secs)
+                .clamp_range(1..=60 * 60)
+                .suffix(" s"),
+        );
+    });
+}
+
+fn invocation_editor(ui: &mut egui::Ui, id: impl std::hash::Hash, invocation: &mut Invocation) {
+    egui::Grid::new(id).show(ui, |ui| {
+        ui.label("Program");
+        ui.add(egui::TextEdit::singleline(&mut invocation.program).hint_text("path"));
+        ui.end_row();
+        ui.label("Arguments");
+        let mut args = invocation.args.join("\n");
+        if ui
+            .add(egui::TextEdit::multiline(&mut args).desired_rows(2))
+            .changed()
+        {
+            invocation.args = args.split('\n').map(str::to_owned).collect();
+        }
+        ui.end_row();
+    });
+}
+
+fn webhook_editor(ui: &mut egui::Ui, id: (usize, usize), webhook: &mut Webhook) {
+    ui.horizontal(|ui| {
+        egui::ComboBox::from_id_source(("webhook_method", id))
+            .selected_text(&webhook.method)
+            .show_ui(ui, |ui| {
+                for method in ["POST", "PUT", "PATCH", "GET", "DELETE"] {
+                    ui.selectable_value(&mut webhook.method, method.to_owned(), method);
+                }
+            });
+        ui.add(egui::TextEdit::singleline(&mut webhook.url).hint_text("https://"));
+    });
+    ui.label("Body");
+    ui.add(
+        egui::TextEdit::multiline(&mut webhook.body)
+            .code_editor()
+            .desired_rows(3),
+    );
+    ui.small(format!(
+        "{} Values are escaped for JSON strings.",
+        PLACEHOLDERS
+    ));
+
+    // Header names are edited on a copy, as they are the keys of the map.
+    let mut headers: Vec<(String, String)> = webhook.headers.clone().into_iter().collect();
+    let mut removed = None;
+    for (index, (name, value)) in headers.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(name).desired_width(120.));
+            ui.add(egui::TextEdit::singleline(value).desired_width(160.));
+            if ui.small_button("Remove").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        headers.remove(index);
+    }
+    if ui.button("Add header").clicked() {
+        headers.push((format!("X-Header-{}", headers.len() + 1), String::new()));
+    }
+    webhook.headers = headers.into_iter().collect();
+
+    ui.horizontal(|ui| {
+        ui.label("Retries");
+        ui.add(egui::DragValue::new(&mut webhook.retries).clamp_range(0..=10));
+    });
+    ui.horizontal(|ui| {
+        ui.label("Sign with secret");
+        ui.add(egui::TextEdit::singleline(&mut webhook.secret).hint_text("webhook secret name"));
+    });
+
+    // Headers whose values are webhook secrets, by the secret's name.
+    let mut secret_headers: Vec<(String, String)> =
+        webhook.secret_headers.clone().into_iter().collect();
+    let mut removed = None;
+    for (index, (name, secret)) in secret_headers.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(name).desired_width(120.));
+            ui.label("from secret");
+            ui.add(egui::TextEdit::singleline(secret).desired_width(120.));
+            if ui.small_button("Remove").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = removed {
+        secret_headers.remove(index);
+    }
+    if ui.button("Add secret header").clicked() {
+        secret_headers.push(("Authorization".to_owned(), String::new()));
+    }
+    webhook.secret_headers = secret_headers.into_iter().collect();
+}
+
+/// A value that is only changed while its box is ticked.
+fn optional_value(ui: &mut egui::Ui, label: &str, value: &mut Option<i32>, suffix: &str) {
+    ui.horizontal(|ui| {
+        let mut set = value.is_some();
+        if ui.checkbox(&mut set, label).changed() {
+            *value = set.then_some(0);
+        }
+        if let Some(value) = value {
+            ui.add(
+                egui::DragValue::new(value)
+                    .clamp_range(0..=i32::MAX)
+                    .suffix(suffix),
+            );
+        }
+    });
+}
+
+fn command_picker(
+    ui: &mut egui::Ui,
+    id: impl std::hash::Hash,
+    command: &mut CommandRef,
+    commands: &[CommandResponse],
+) {
+    if ui.text_edit_singleline(&mut command.command_name).changed() {
+        command.command_id.clear();
+    }
+    if commands.is_empty() {
+        return;
+    }
+    egui::ComboBox::from_id_source(("command_picker", id))
+        .selected_text("Pick")
+        .show_ui(ui, |ui| {
+            for found in commands {
+                let selected = found._id == command.command_id;
+                if ui.selectable_label(selected, &found.command).clicked() {
+                    command.command_name = found.command.clone();
+                    command.command_id = found._id.clone();
+                }
+            }
+        });
+}
+
+fn command_list(ui: &mut egui::Ui, commands: &[CommandResponse]) {
+    egui::ScrollArea::both()
+        .id_source("command_list")
+        .max_height(120.)
+        .show(ui, |ui| {
+            egui::Grid::new("command_grid")
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Name");
+                    ui.strong("Online");
+                    ui.strong("Offline");
+                    ui.strong("Cooldown");
+                    ui.strong("Reply");
+                    ui.end_row();
+                    for command in commands {
+                        ui.label(&command.command);
+                        ui.label(if command.enabledOnline { "yes" } else { "no" });
+                        ui.label(if command.enabledOffline { "yes" } else { "no" });
+                        ui.label(format!(
+                            "{}s / {}s",
+                            command.cooldown.user, command.cooldown.global
+                        ));
+                        ui.label(&command.reply);
+                        ui.end_row();
+                    }
+                });
+        });
+}
+
+impl FishingeSetup {
+    /// Returns what to start once "Start listening" is clicked.
+    fn show(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) -> Option<Launch> {
+        self.form.poll_commands(ctx);
+        self.poll_validation(ctx);
+        self.poll_profile(ctx);
+        let launch = self.poll_saving(ctx);
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if let Some(err) = &self.load_error {
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!(
+                        "The existing config could not be read and will be replaced when starting. A backup is kept.\n{}",
+                        err
+                    ),
+                );
+            }
+            self.profiles(ui);
+            self.form.fields(ui);
+            self.validation_view(ui);
             ui.with_layout(egui::Layout::left_to_right(egui::Align::BOTTOM), |ui| {
+                if ui
+                    .add_enabled(self.can_launch(), egui::Button::new("Start listening"))
+                    .clicked()
+                {
+                    let config = self.form.config.clone();
+                    let launch = Launch {
+                        also_run: self
+                            .also_run
+                            .iter()
+                            .filter(|profile| **profile != config.profile)
+                            .cloned()
+                            .collect(),
+                        config: config.clone(),
+                    };
+                    self.saving = Some((launch, spawn_blocking(move || config.write())));
+                }
+                if self.saving.is_some() {
+                    ui.spinner();
+                }
+            });
+        });
+        launch
+    }
+
+    /// Returns what to start once its config is saved.
+    fn poll_saving(&mut self, ctx: &egui::Context) -> Option<Launch> {
+        let (launch, request) = self.saving.take()?;
+        let mut request = Some(request);
+        match poll_blocking(&mut request, ctx) {
+            None => {
+                self.saving = request.map(|request| (launch, request));
+                None
+            }
+            Some(Ok(())) => {
+                self.load_error = None;
+                // Only the first channel started with it logs the migration.
+                self.form.config.migrated = None;
+                Some(launch)
+            }
+            Some(Err(err)) => {
+                self.load_error = Some(format!("Could not save config: {:#}", err));
+                None
+            }
+        }
+    }
+}
+
+/// Runs blocking work off the UI thread, such as deriving the key of
+/// encrypted credentials, see [`poll_blocking`].
+fn spawn_blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> oneshot::Receiver<Result<T>> {
+    let (tx, rx) = oneshot::channel();
+    tokio::task::spawn_blocking(move || {
+        let _ = tx.send(work());
+    });
+    rx
+}
+
+/// Takes the result of the work once it is done, repainting until then.
+fn poll_blocking<T>(
+    request: &mut Option<oneshot::Receiver<Result<T>>>,
+    ctx: &egui::Context,
+) -> Option<Result<T>> {
+    let mut receiver = request.take()?;
+    match receiver.try_recv() {
+        Err(TryRecvError::Empty) => {
+            *request = Some(receiver);
+            ctx.request_repaint();
+            None
+        }
+        Ok(result) => Some(result),
+        Err(TryRecvError::Closed) => Some(Err(anyhow!("the work panicked"))),
+    }
+}
+
+/// Single-line text field that hides its content.
+fn secret_field(ui: &mut egui::Ui, secret: &mut String) {
+    ui.add(egui::TextEdit::singleline(secret).password(true));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Setup,
+    Dashboard,
+    History,
+    Stats,
+    Settings,
+}
+
+struct Fishinge {
+    view: View,
+    setup: FishingeSetup,
+    channels: Vec<Channel>,
+    selected: usize,
+    /// Settings being edited for the selected channel.
+    settings: Option<SettingsForm>,
+    history: HistoryView,
+    /// Where the last report was exported to, or why it failed.
+    report_message: Option<String>,
+    /// Profiles whose config is being loaded to start them, as unlocking
+    /// the credentials takes a while.
+    loading: Vec<(String, oneshot::Receiver<Result<Config>>)>,
+}
+
+impl Fishinge {
+    fn new(setup: FishingeSetup) -> Fishinge {
+        Fishinge {
+            view: View::Setup,
+            setup,
+            channels: Vec::new(),
+            selected: 0,
+            settings: None,
+            history: HistoryView::default(),
+            report_message: None,
+            loading: Vec::new(),
+        }
+    }
+
+    fn launch(&mut self, launch: Launch) {
+        let name = launch.config.profile.clone();
+        self.start(launch.config);
+        for name in &launch.also_run {
+            if self.channel(name).is_none() {
+                self.load(name, None);
+            }
+        }
+        self.selected = self.channel(&name).unwrap_or_default();
+        self.view = View::Dashboard;
+    }
+
+    fn channel(&self, name: &str) -> Option<usize> {
+        self.channels
+            .iter()
+            .position(|channel| channel.name == name)
+    }
+
+    /// Starts a channel for the profile, replacing a stopped one. A running
+    /// one gets the config applied instead, so its listener and open
+    /// windows are left alone.
+    fn start(&mut self, config: Config) {
+        match self.channel(&config.profile) {
+            Some(index) if *self.channels[index].status() == ChannelStatus::Running => {
+                self.channels[index].send(Control::Apply(Box::new(config)));
+            }
+            Some(index) => self.channels[index] = Channel::start(config),
+            None => self.channels.push(Channel::start(config)),
+        }
+    }
+
+    /// Shows why the profile could not start, in its channel if it has a
+    /// running one.
+    fn fail(&mut self, name: &str, error: Error) {
+        match self.channel(name) {
+            Some(index) if *self.channels[index].status() == ChannelStatus::Running => {
+                error::report(&self.channels[index].output, &error);
+            }
+            Some(index) => self.channels[index] = Channel::failed(name, error),
+            None => self.channels.push(Channel::failed(name, error)),
+        }
+    }
+
+    /// Starts the channel again with the config on disk, or the one it ran
+    /// with if that cannot be used.
+    fn restart(&mut self, index: usize) {
+        let channel = &self.channels[index];
+        let (name, running) = (channel.name.clone(), channel.config());
+        self.load(&name, running);
+    }
+
+    /// Loads the profile on a blocking thread and starts it once loaded,
+    /// see [`Fishinge::poll_loading`]. `fallback` is used if the config on
+    /// disk cannot be.
+    fn load(&mut self, name: &str, fallback: Option<Config>) {
+        if self.is_loading(name) {
+            return;
+        }
+        let profile = name.to_owned();
+        let rx = spawn_blocking(move || match (Config::load_profile(&profile), fallback) {
+            (Ok(config), _) if !config.credentials_locked => Ok(config),
+            (_, Some(fallback)) => Ok(fallback),
+            (loaded, None) => loaded,
+        });
+        self.loading.push((name.to_owned(), rx));
+    }
+
+    fn is_loading(&self, name: &str) -> bool {
+        self.loading.iter().any(|(loading, _)| loading == name)
+    }
+
+    fn poll_loading(&mut self, ctx: &egui::Context) {
+        for (name, mut request) in std::mem::take(&mut self.loading) {
+            match request.try_recv() {
+                Err(TryRecvError::Empty) => {
+                    self.loading.push((name, request));
+                    ctx.request_repaint();
+                }
+                Ok(loaded) => match startable(&name, loaded) {
+                    Ok(config) => self.start(config),
+                    Err(err) => self.fail(&name, err),
+                },
+                Err(TryRecvError::Closed) => self.fail(
+                    &name,
+                    Error::Internal("Loading the config panicked".to_owned()),
+                ),
+            }
+        }
+    }
+
+    fn navigation(&mut self, ui: &mut egui::Ui, frame: &mut eframe::Frame) {
+        ui.horizontal(|ui| {
+            for (view, name) in [
+                (View::Setup, "Setup"),
+                (View::Dashboard, "Dashboard"),
+                (View::History, "History"),
+                (View::Stats, "Stats"),
+                (View::Settings, "Settings"),
+            ] {
+                if ui.selectable_label(self.view == view, name).clicked() && self.view != view {
+                    self.view = view;
+                    self.settings = None;
+                }
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                 if ui.button("Quit").clicked() {
                     frame.close();
-                    std::process::exit(0);
                 }
             });
-            if self.handle.is_some() {
-                let handle = self.handle.take().unwrap();
-                if handle.is_finished() {
-                    write_expect!(self.output, "Listen handler closed!\n  CANNOT CONTINUE!");
-                    match handle.join().unwrap() {
-                        Ok(_) => write_expect!(self.output, "Handler closed correctly"),
-                        Err(err) => {
-                            write_expect!(self.output, &format!("Handler failed: {:?}", err))
-                        }
+        });
+    }
+
+    fn channel_tabs(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            for (index, channel) in self.channels.iter().enumerate() {
+                if ui
+                    .selectable_label(index == self.selected, status_label(channel))
+                    .clicked()
+                    && index != self.selected
+                {
+                    self.selected = index;
+                    self.settings = None;
+                }
+            }
+        });
+        ui.separator();
+    }
+
+    fn dashboard(&mut self, ui: &mut egui::Ui) {
+        let loading = matches!(
+            self.channels.get(self.selected),
+            Some(channel) if self.is_loading(&channel.name)
+        );
+        let Some(channel) = self.channels.get_mut(self.selected) else {
+            return;
+        };
+        let mut restart = false;
+        ui.horizontal(|ui| match channel.status() {
+            ChannelStatus::Running => {
+                if ui.button("Stop listening").clicked() {
+                    channel.request_stop();
+                }
+                if ui
+                    .button("Reload config")
+                    .on_hover_text("Config changes on disk are also picked up automatically.")
+                    .clicked()
+                {
+                    channel.send(Control::Reload);
+                }
+            }
+            ChannelStatus::Stopping => {
+                ui.spinner();
+                ui.label("Closing open windows...");
+            }
+            ChannelStatus::Stopped | ChannelStatus::Failed(_) if loading => {
+                ui.spinner();
+                ui.label("Loading config...");
+            }
+            ChannelStatus::Stopped | ChannelStatus::Failed(_) => {
+                restart = ui.button("Start listening").clicked();
+            }
+        });
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            let text = channel
+                .output
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone();
+            ui.horizontal_wrapped(|ui| {
+                ui.monospace(text);
+            });
+        });
+        if restart {
+            self.restart(self.selected);
+        }
+    }
+
+    /// The profile whose history is shown: the selected channel, or the one
+    /// in the setup view before anything runs.
+    fn history_profile(&self) -> String {
+        match self.channels.get(self.selected) {
+            Some(channel) => channel.name.clone(),
+            None => self.setup.form.config.profile.clone(),
+        }
+    }
+
+    fn stats(&mut self, ui: &mut egui::Ui, profile: &str) {
+        self.history.refresh(profile);
+        if let Some(err) = &self.history.error {
+            ui.colored_label(egui::Color32::RED, err);
+        }
+        let report = Report::new(profile, &self.history.entries);
+        ui.horizontal(|ui| {
+            if ui.button("Export report").clicked() {
+                self.report_message = Some(export_report(&report, "report"));
+            }
+            if ui.button("Export last stream").clicked() {
+                let report = Report::last_stream(profile, &self.history.entries);
+                self.report_message = Some(export_report(&report, "stream-report"));
+            }
+        });
+        if let Some(message) = &self.report_message {
+            ui.small(message);
+        }
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            ui.heading("Total");
+            summary_view(ui, "total", &report.total);
+            ui.heading("Streams");
+            ui.small(format!(
+                "Redemptions more than {} hours apart count as separate streams.",
+                STREAM_GAP_HOURS
+            ));
+            periods_view(ui, "streams", &report.streams, "%Y-%m-%d %H:%M");
+            ui.heading("Days");
+            periods_view(ui, "days", &report.days, "%Y-%m-%d");
+        });
+    }
+
+    fn settings(&mut self, ui: &mut egui::Ui) {
+        let Some(channel) = self.channels.get(self.selected) else {
+            return;
+        };
+        if self.settings.is_none() {
+            self.settings = channel.config().map(SettingsForm::new);
+        }
+        let Some(form) = &mut self.settings else {
+            ui.label("This channel never started, set it up in the setup view.");
+            return;
+        };
+        if let Some(saved) = poll_blocking(&mut form.save_request, ui.ctx()) {
+            let saved = match saved {
+                Ok(_) => "Saved config.".to_owned(),
+                Err(err) => format!("Could not save config: {:#}", err),
+            };
+            write_output(&channel.output, &saved);
+        }
+        let running = *channel.status() == ChannelStatus::Running;
+        let mut reset = false;
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            form.fields(ui);
+            ui.separator();
+            if running {
+                ui.label("Changes are checked before they replace the running config.");
+            }
+            ui.horizontal(|ui| {
+                if running && ui.button("Apply").clicked() {
+                    channel.send(Control::Apply(Box::new(form.config.clone())));
+                }
+                let saving = form.save_request.is_some();
+                if !running && ui.add_enabled(!saving, egui::Button::new("Save")).clicked() {
+                    let config = form.config.clone();
+                    form.save_request = Some(spawn_blocking(move || config.write()));
+                }
+                if saving {
+                    ui.spinner();
+                }
+                reset = ui.button("Reset").clicked();
+            });
+        });
+        if reset {
+            self.settings = None;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    RedeemedAt,
+    User,
+    Reward,
+    Cost,
+    Input,
+    Outcome,
+    Duration,
+}
+
+/// The persisted redemption history of one profile, reloaded whenever the
+/// file changes.
+#[derive(Default)]
+struct HistoryView {
+    profile: String,
+    modified: Option<SystemTime>,
+    entries: Vec<Entry>,
+    error: Option<String>,
+    sort: Option<Column>,
+    descending: bool,
+    user: String,
+    /// Dates as `YYYY-MM-DD`, both inclusive.
+    from: String,
+    to: String,
+    export_message: Option<String>,
+}
+
+impl HistoryView {
+    fn refresh(&mut self, profile: &str) {
+        let modified = history::modified(profile);
+        if self.profile == profile && self.modified == modified && self.error.is_none() {
+            return;
+        }
+        if self.profile != profile {
+            self.export_message = None;
+        }
+        self.profile = profile.to_owned();
+        self.modified = modified;
+        match history::load(profile) {
+            Ok(entries) => {
+                self.entries = entries;
+                self.error = None;
+            }
+            Err(err) => self.error = Some(format!("{:#}", err)),
+        }
+    }
+
+    fn filtered(&self) -> Vec<&Entry> {
+        let user = self.user.to_lowercase();
+        let mut entries: Vec<&Entry> = self
+            .entries
+            .iter()
+            .filter(|entry| {
+                user.is_empty()
+                    || entry.user_name.to_lowercase().contains(&user)
+                    || entry.user_login.contains(&user)
+            })
+            .filter(|entry| {
+                let date = entry.redeemed_at.format("%Y-%m-%d").to_string();
+                (self.from.is_empty() || date >= self.from)
+                    && (self.to.is_empty() || date <= self.to)
+            })
+            .collect();
+        match self.sort {
+            None | Some(Column::RedeemedAt) => entries.sort_by_key(|entry| entry.redeemed_at),
+            Some(Column::User) => entries.sort_by_key(|entry| entry.user_name.to_lowercase()),
+            Some(Column::Reward) => entries.sort_by(|a, b| a.reward_title.cmp(&b.reward_title)),
+            Some(Column::Cost) => entries.sort_by_key(|entry| entry.cost),
+            Some(Column::Input) => entries.sort_by(|a, b| a.user_input.cmp(&b.user_input)),
+            Some(Column::Outcome) => entries.sort_by_key(|entry| entry.outcome.to_string()),
+            Some(Column::Duration) => entries.sort_by_key(|entry| entry.duration_secs),
+        }
+        // Newest first unless a column was picked.
+        if self.descending || self.sort.is_none() {
+            entries.reverse();
+        }
+        entries
+    }
+
+    fn export(&mut self, entries: &[Entry]) {
+        let result = profile::dir(&self.profile).and_then(|dir| {
+            let path = dir.join(format!(
+                "history-{}.csv",
+                Utc::now().format("%Y-%m-%d-%H%M%S")
+            ));
+            history::export_csv(entries, &path)?;
+            Ok(path)
+        });
+        self.export_message = Some(match result {
+            Ok(path) => format!(
+                "Exported {} redemptions to {}",
+                entries.len(),
+                path.display()
+            ),
+            Err(err) => format!("Export failed: {:#}", err),
+        });
+    }
+
+    fn header(&mut self, ui: &mut egui::Ui, column: Column, name: &str) {
+        let arrow = match (self.sort == Some(column), self.descending) {
+            (false, _) => "",
+            (true, false) => " ⏶",
+            (true, true) => " ⏷",
+        };
+        if ui
+            .selectable_label(self.sort == Some(column), format!("{}{}", name, arrow))
+            .clicked()
+        {
+            if self.sort == Some(column) {
+                self.descending = !self.descending;
+            } else {
+                self.sort = Some(column);
+                self.descending = false;
+            }
+        }
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui, profile: &str) {
+        self.refresh(profile);
+        if let Some(err) = &self.error {
+            ui.colored_label(egui::Color32::RED, err);
+        }
+        ui.horizontal(|ui| {
+            ui.label("User");
+            ui.add(egui::TextEdit::singleline(&mut self.user).desired_width(80.));
+            ui.label("From");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.from)
+                    .hint_text("YYYY-MM-DD")
+                    .desired_width(80.),
+            );
+            ui.label("To");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.to)
+                    .hint_text("YYYY-MM-DD")
+                    .desired_width(80.),
+            );
+        });
+        let entries = self.filtered();
+        let mut export = false;
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "{} of {} redemptions (UTC)",
+                entries.len(),
+                self.entries.len()
+            ));
+            export = ui
+                .add_enabled(!entries.is_empty(), egui::Button::new("Export CSV"))
+                .clicked();
+        });
+        let entries: Vec<Entry> = entries.into_iter().cloned().collect();
+        if export {
+            self.export(&entries);
+        }
+        if let Some(message) = &self.export_message {
+            ui.small(message);
+        }
+        egui::ScrollArea::both().show(ui, |ui| {
+            egui::Grid::new("history").striped(true).show(ui, |ui| {
+                self.header(ui, Column::RedeemedAt, "Redeemed at");
+                self.header(ui, Column::User, "User");
+                self.header(ui, Column::Reward, "Reward");
+                self.header(ui, Column::Cost, "Cost");
+                self.header(ui, Column::Input, "Input");
+                self.header(ui, Column::Outcome, "Outcome");
+                self.header(ui, Column::Duration, "Duration");
+                ui.end_row();
+                for entry in &entries {
+                    ui.label(entry.redeemed_at.format("%Y-%m-%d %H:%M:%S").to_string());
+                    ui.label(&entry.user_name);
+                    ui.label(&entry.reward_title);
+                    ui.label(entry.cost.to_string());
+                    ui.label(&entry.user_input);
+                    if entry.outcome.is_failure() {
+                        ui.colored_label(egui::Color32::RED, entry.outcome.to_string());
+                    } else {
+                        ui.label(entry.outcome.to_string());
                     }
-                } else {
-                    let _ = self.handle.insert(handle);
+                    ui.label(format_duration(Duration::from_secs(entry.duration_secs)));
+                    ui.end_row();
                 }
+            });
+        });
+    }
+}
+
+fn summary_view(ui: &mut egui::Ui, id: &str, summary: &Summary) {
+    egui::Grid::new((id, "summary")).show(ui, |ui| {
+        ui.label("Redemptions");
+        ui.label(summary.redemptions.to_string());
+        ui.end_row();
+        ui.label("Points spent");
+        ui.label(summary.points_spent.to_string());
+        ui.end_row();
+        ui.label("Minutes enabled");
+        ui.label(summary.enabled_minutes().to_string());
+        ui.end_row();
+        ui.label("Failures");
+        ui.label(format!(
+            "{} ({:.1}%)",
+            summary.failures,
+            summary.failure_rate() * 100.
+        ));
+        ui.end_row();
+    });
+    if summary.top_redeemers.is_empty() {
+        return;
+    }
+    egui::Grid::new((id, "redeemers"))
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("Top redeemer");
+            ui.strong("Redemptions");
+            ui.strong("Points");
+            ui.end_row();
+            for redeemer in &summary.top_redeemers {
+                ui.label(&redeemer.user_name);
+                ui.label(redeemer.redemptions.to_string());
+                ui.label(redeemer.points_spent.to_string());
+                ui.end_row();
             }
-            Ok(())
         });
+}
+
+/// Newest first, each with its own top redeemers when expanded.
+fn periods_view(ui: &mut egui::Ui, id: &str, periods: &[Period], format: &str) {
+    for period in periods.iter().rev() {
+        egui::CollapsingHeader::new(format!(
+            "{}: {} redemptions, {} minutes, {:.1}% failed",
+            period.start.format(format),
+            period.summary.redemptions,
+            period.summary.enabled_minutes(),
+            period.summary.failure_rate() * 100.
+        ))
+        .id_source((id, period.start))
+        .show(ui, |ui| {
+            summary_view(ui, &format!("{}{}", id, period.start), &period.summary);
+        });
+    }
+}
+
+fn export_report(report: &Report, name: &str) -> String {
+    let name = format!("{}-{}", name, Utc::now().format("%Y-%m-%d-%H%M%S"));
+    match profile::dir(&report.profile).and_then(|dir| report.export(&dir, &name)) {
+        Ok((json, markdown)) => format!(
+            "Exported report to {} and {}",
+            json.display(),
+            markdown.display()
+        ),
+        Err(err) => format!("Export failed: {:#}", err),
+    }
+}
+
+fn status_label(channel: &Channel) -> egui::RichText {
+    let (icon, color) = match channel.status() {
+        ChannelStatus::Running => ("●", egui::Color32::GREEN),
+        ChannelStatus::Stopping => ("◌", egui::Color32::YELLOW),
+        ChannelStatus::Stopped => ("■", egui::Color32::GRAY),
+        ChannelStatus::Failed(_) => ("✖", egui::Color32::RED),
+    };
+    egui::RichText::new(format!("{} {}", icon, channel.name)).color(color)
+}
+
+/// The loaded config of a profile, e.g. one running alongside the one in
+/// the setup view, or why it cannot be started.
+fn startable(name: &str, loaded: Result<Config>) -> std::result::Result<Config, Error> {
+    match loaded {
+        Ok(config) if config.credentials_locked => Err(Error::Config(anyhow!(
+            "the credentials of profile \"{}\" are encrypted, set {} or unlock it in the setup view",
+            name,
+            credentials::PASSPHRASE_VAR
+        ))),
+        Ok(config) => Ok(config),
+        Err(err) => Err(Error::Config(
+            err.context(format!("could not load profile \"{}\"", name)),
+        )),
+    }
+}
+
+impl eframe::App for Fishinge {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        if !self.channels.is_empty() {
+            ctx.request_repaint();
+        }
+        for channel in &mut self.channels {
+            channel.poll();
+        }
+        self.poll_loading(ctx);
+        if let Some(form) = &mut self.settings {
+            form.poll_commands(ctx);
+        }
+        egui::TopBottomPanel::top("navigation").show(ctx, |ui| self.navigation(ui, frame));
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if self.view == View::Setup {
+                if let Some(launch) = self.setup.show(ui, ctx) {
+                    self.launch(launch);
+                }
+                return;
+            }
+            if !self.channels.is_empty() {
+                self.channel_tabs(ui);
+            }
+            match self.view {
+                View::History => {
+                    let profile = self.history_profile();
+                    self.history.show(ui, &profile);
+                }
+                View::Stats => {
+                    let profile = self.history_profile();
+                    self.stats(ui, &profile);
+                }
+                _ if self.channels.is_empty() => {
+                    ui.label("Not listening yet, start from the setup view.");
+                }
+                View::Dashboard => self.dashboard(ui),
+                View::Settings => self.settings(ui),
+                View::Setup => {}
+            }
+        });
+    }
+
+    /// Closing the window never starts anything; running channels close
+    /// their open windows before the app exits.
+    fn on_close_event(&mut self) -> bool {
+        for channel in &mut self.channels {
+            channel.request_stop();
+        }
+        true
     }
 }
 
 fn main() -> Result<()> {
     let options = eframe::NativeOptions {
-        initial_window_size: Some(egui::vec2(340., 380.)),
+        initial_window_size: Some(egui::vec2(420., 480.)),
         resizable: true,
         fullscreen: false,
         maximized: false,
         ..Default::default()
     };
 
-    let options_clone = options.clone();
-
-    let config = match Config::load() {
-        Ok(config) => config,
-        Err(_) => Config::empty(),
-    };
-
-    eframe::run_native(
-        "Fishinge Setup",
-        options_clone,
-        Box::new(|_cc| Box::new(FishingeSetup { config })),
-    );
+    let args = Args::parse()?;
+    let (config, load_error) = load_config(&args.profiles[0])?;
+    let setup = FishingeSetup::new(config, load_error, args.profiles[1..].to_vec());
 
-    let (tx, rx) = mpsc::channel();
-    let (fish_tx, fish_rx) = mpsc::channel();
-
-    let output = Arc::new(Mutex::new(String::new()));
-    let output_write1 = Arc::clone(&output);
-    let output_write2 = Arc::clone(&output);
-    let output_write3 = Arc::clone(&output);
-    let output_read = Arc::clone(&output);
-
-    let url = get_default_url()?;
-    let_match_writer!(event_res, event_handler(url, tx.clone()), output_write1);
-
-    let config = Config::load()
-        .expect("config has to exist at this point, unless some system operation failed");
-    let config2 = config.clone();
-
-    let notification_handle = thread::Builder::new().name("notifications".into()).spawn(
-        move || -> Result<(), anyhow::Error> {
-            if_err_writer!(config.test(), output_write2, drop(fish_rx));
-            if_err_writer!(fish_rx.recv(), output_write2,);
-            loop {
-                if_err_writer!(fish_rx.recv(), output_write2,);
-                handle_notification(&output_write2, &config);
-            }
-        },
-    )?;
-
-    let listener_handle = thread::Builder::new().name("listener".into()).spawn(
-        move || -> Result<(), anyhow::Error> {
-            let mut welcome_count = 0;
-            if_err_writer!(fish_tx.send("Healthy!"), output_write3,);
-            loop {
-                if notification_handle.is_finished() {
-                    write_expect!(&output_write3, "Notification handler closed!");
-                    match notification_handle.join() {
-                        Ok(ok) => return Ok(ok?),
-                        Err(err) => return Err(anyhow::anyhow!("notification handler died: {:#?}", err)),
-                    }
-                }
-                let_match_writer!(msg, rx.recv(), output_write3);
-                match msg {
-                    TwitchMessage::Notification(_) => {
-                        if_err_writer!(fish_tx.send("Ping!"), output_write3,);
-                    }
-                    TwitchMessage::Welcome(msg) => {
-                        welcome_count += 1;
-                        if welcome_count == 1 {
-                            write_expect!(&output_write3, "Connected to Twitch!");
-                        } else {
-                            write_expect!(&output_write3, "Reconnected to Twitch!");
-                        }
-                        let session_id = msg.payload.session.id.to_owned();
-                        if !is_subscribed(&config2, session_id.clone())? {
-                            if_err_writer!(
-                                subscribe(&output_write3, session_id, &config2),
-                                output_write3,
-                            );
-                            write_expect!(&output_write3, "Subscribed to redemption notifications!\nWaiting for redmeptions...");
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        },
-    )?;
+    // Channels and requests run as tasks on it, the UI only polls their results.
+    let runtime = tokio::runtime::Runtime::new()?;
+    let _entered = runtime.enter();
 
+    // Dropping the app stops all channels.
     eframe::run_native(
         "Pond opener 3000™",
         options,
-        Box::new(move |_cc| {
-            Box::new(FishingeOutput {
-                output: output_read,
-                handle: Some(listener_handle),
-            })
-        }),
+        Box::new(|_cc| Box::new(Fishinge::new(setup))),
     );
-
-    event_res
-        .session
-        .lock()
-        .expect("session should not be poisoned")
-        .socket
-        .close(Some(CloseFrame {
-            code: CloseCode::Normal,
-            reason: "Client encountered error.".into(),
-        }))?;
     Ok(())
 }
 
-fn handle_notification(output: &Arc<Mutex<String>>, config: &Config) {
-    if let Err(err) = fishinge::update_command(output, config) {
-        write_expect!(
-            output,
-            &format!("Error updating command: {}\nAttempting to continue...", err)
-        );
+/// Command line options, e.g. `fishinge --profile other_streamer`.
+///
+/// `--profile` may be given several times to run multiple channels at once;
+/// the first one is shown in the setup view.
+struct Args {
+    profiles: Vec<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Args> {
+        let mut args = Args {
+            profiles: Vec::new(),
+        };
+        let mut argv = std::env::args().skip(1);
+        while let Some(arg) = argv.next() {
+            if arg == "--profile" {
+                let profile = argv
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--profile needs a name"))?;
+                args.add_profile(profile);
+            } else if let Some(profile) = arg.strip_prefix("--profile=") {
+                args.add_profile(profile.to_owned());
+            } else {
+                return Err(anyhow::anyhow!("unknown argument \"{}\"", arg));
+            }
+        }
+        if args.profiles.is_empty() {
+            args.profiles.push(profile::DEFAULT.to_owned());
+        }
+        for profile in &args.profiles {
+            profile::dir(profile)?;
+        }
+        Ok(args)
+    }
+
+    fn add_profile(&mut self, profile: String) {
+        if !self.profiles.contains(&profile) {
+            self.profiles.push(profile);
+        }
     }
 }
 
-fn subscribe(output: &Arc<Mutex<String>>, session_id: String, config: &Config) -> Result<()> {
-    let_match_writer!((broadcaster_id, reward_id), get_ids(config), *output);
-    write_expect!(
-        output,
-        &format!(
-            "Found all required ids:\n Broadcaster:\n  {}\n Reward:\n  {}",
-            broadcaster_id, reward_id
-        )
-    );
-    create_subscription(config, session_id, broadcaster_id, reward_id)
+/// Loads a profile for the setup view. A profile that exists but cannot be
+/// read is replaced by an empty one, together with the reason why.
+/// A config with the error that kept the one on disk from loading.
+type LoadedConfig = (Config, Option<String>);
+
+fn load_config(profile: &str) -> Result<LoadedConfig> {
+    match Config::load_profile(profile) {
+        Ok(config) => Ok((config, None)),
+        Err(err) if Config::exists(profile) => {
+            Ok((Config::new_profile(profile)?, Some(format!("{:#}", err))))
+        }
+        Err(_) => Ok((Config::new_profile(profile)?, None)),
+    }
 }
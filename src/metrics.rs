@@ -0,0 +1,185 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use prometheus::{Encoder, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Counters and gauges of every channel running in this process, labelled
+/// by profile where they belong to one.
+struct Metrics {
+    registry: Registry,
+    redemptions: IntCounterVec,
+    actions: IntCounterVec,
+    http_requests: IntCounterVec,
+    reconnects: IntCounterVec,
+    window_open: IntGaugeVec,
+    window_remaining: IntGaugeVec,
+    token_expiry: IntGaugeVec,
+    /// When the open windows close, to work out the time remaining when scraped.
+    closes_at: Mutex<HashMap<(String, String), Instant>>,
+}
+
+static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter =
+        IntCounterVec::new(Opts::new(name, help), labels).expect("metric definitions are valid");
+    registry
+        .register(Box::new(counter.clone()))
+        .expect("metrics are registered once");
+    counter
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge =
+        IntGaugeVec::new(Opts::new(name, help), labels).expect("metric definitions are valid");
+    registry
+        .register(Box::new(gauge.clone()))
+        .expect("metrics are registered once");
+    gauge
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+        Metrics {
+            redemptions: counter(
+                &registry,
+                "fishinge_redemptions_total",
+                "Redemptions received, by what came of them.",
+                &["profile", "reward", "outcome"],
+            ),
+            actions: counter(
+                &registry,
+                "fishinge_actions_total",
+                "Actions started, stopped or reminded, by whether that worked.",
+                &["profile", "action", "phase", "result"],
+            ),
+            http_requests: counter(
+                &registry,
+                "fishinge_http_requests_total",
+                "Requests to outside APIs, by status code or \"error\" if there was no response.",
+                &["api", "status"],
+            ),
+            reconnects: counter(
+                &registry,
+                "fishinge_eventsub_reconnects_total",
+                "Times the EventSub connection was established again.",
+                &["profile"],
+            ),
+            window_open: gauge(
+                &registry,
+                "fishinge_window_open",
+                "Whether the binding's window is open.",
+                &["profile", "reward"],
+            ),
+            window_remaining: gauge(
+                &registry,
+                "fishinge_window_remaining_seconds",
+                "Time until the binding's window closes.",
+                &["profile", "reward"],
+            ),
+            token_expiry: gauge(
+                &registry,
+                "fishinge_token_expiry_timestamp_seconds",
+                "When the Twitch user access token expires, as a Unix timestamp.",
+                &["profile"],
+            ),
+            closes_at: Mutex::new(HashMap::new()),
+            registry,
+        }
+    }
+}
+
+pub fn redeemed(profile: &str, reward: &str, outcome: &str) {
+    METRICS
+        .redemptions
+        .with_label_values(&[profile, reward, outcome])
+        .inc();
+}
+
+/// `phase` is one of "start", "stop" and "remind".
+pub fn action(profile: &str, action: &str, phase: &str, ok: bool) {
+    let result = if ok { "ok" } else { "failed" };
+    METRICS
+        .actions
+        .with_label_values(&[profile, action, phase, result])
+        .inc();
+}
+
+pub fn reconnected(profile: &str) {
+    METRICS.reconnects.with_label_values(&[profile]).inc();
+}
+
+pub fn window_opened(profile: &str, reward: &str, closes_at: Instant) {
+    METRICS
+        .window_open
+        .with_label_values(&[profile, reward])
+        .set(1);
+    if let Ok(mut windows) = METRICS.closes_at.lock() {
+        windows.insert((profile.to_owned(), reward.to_owned()), closes_at);
+    }
+}
+
+pub fn window_closed(profile: &str, reward: &str) {
+    METRICS
+        .window_open
+        .with_label_values(&[profile, reward])
+        .set(0);
+    METRICS
+        .window_remaining
+        .with_label_values(&[profile, reward])
+        .set(0);
+    if let Ok(mut windows) = METRICS.closes_at.lock() {
+        windows.remove(&(profile.to_owned(), reward.to_owned()));
+    }
+}
+
+pub fn token_expires(profile: &str, at: DateTime<Utc>) {
+    METRICS
+        .token_expiry
+        .with_label_values(&[profile])
+        .set(at.timestamp());
+}
+
+/// Counts the response of every request sent through it.
+#[async_trait]
+pub trait Tracked {
+    /// `api` names the API for the metrics, like "helix".
+    async fn send_tracked(self, api: &str) -> reqwest::Result<reqwest::Response>;
+}
+
+#[async_trait]
+impl Tracked for reqwest::RequestBuilder {
+    async fn send_tracked(self, api: &str) -> reqwest::Result<reqwest::Response> {
+        let result = self.send().await;
+        let status = match &result {
+            Ok(response) => response.status().as_u16().to_string(),
+            Err(_) => "error".to_owned(),
+        };
+        METRICS
+            .http_requests
+            .with_label_values(&[api, &status])
+            .inc();
+        result
+    }
+}
+
+/// All metrics in the Prometheus text format.
+pub fn render() -> Result<String> {
+    let now = Instant::now();
+    if let Ok(windows) = METRICS.closes_at.lock() {
+        for ((profile, reward), closes_at) in windows.iter() {
+            METRICS
+                .window_remaining
+                .with_label_values(&[profile, reward])
+                .set(closes_at.saturating_duration_since(now).as_secs() as i64);
+        }
+    }
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&METRICS.registry.gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
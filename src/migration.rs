@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{read, read_to_string};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+use crate::credentials;
+
+pub const CURRENT_VERSION: u32 = 3;
+
+/// Files without a `version` field predate versioning.
+const UNVERSIONED: u32 = 1;
+
+type Migration = fn(&mut Table) -> Result<()>;
+
+/// `MIGRATIONS[i]` upgrades a config from version `i + 1` to `i + 2`.
+const MIGRATIONS: [Migration; 2] = [single_reward_to_bindings, commands_to_actions];
+
+pub fn current_version() -> u32 {
+    CURRENT_VERSION
+}
+
+/// Upgrades the raw config in place, returning the version it had before.
+pub fn migrate(value: &mut Value) -> Result<u32> {
+    let table = value
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("config is not a table"))?;
+    let version = match table.get("version") {
+        None => UNVERSIONED,
+        Some(Value::Integer(version)) if *version >= 1 => *version as u32,
+        Some(version) => return Err(anyhow!("invalid config version {}", version)),
+    };
+    if version > CURRENT_VERSION {
+        return Err(anyhow!(
+            "config version {} is newer than the supported version {}",
+            version,
+            CURRENT_VERSION
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        migration(table)
+            .with_context(|| format!("Failed migrating config to version {}", index + 2))?;
+        table.insert("version".to_owned(), Value::Integer(index as i64 + 2));
+    }
+    Ok(version)
+}
+
+/// Version 1 had a single `reward_title`, `command_name` and `command_id`
+/// at the top level and a fixed five minute duration.
+fn single_reward_to_bindings(table: &mut Table) -> Result<()> {
+    let mut binding = Table::new();
+    for key in ["reward_title", "command_name", "command_id"] {
+        if let Some(value) = table.remove(key) {
+            binding.insert(key.to_owned(), value);
+        }
+    }
+    if !binding.contains_key("reward_title") || !binding.contains_key("command_name") {
+        return Err(anyhow!("missing reward_title or command_name"));
+    }
+    binding.insert("duration_secs".to_owned(), Value::Integer(300));
+    table.insert(
+        "bindings".to_owned(),
+        Value::Array(vec![Value::Table(binding)]),
+    );
+    Ok(())
+}
+
+/// Version 2 bound each reward to exactly one command through `command_name`
+/// and `command_id`, which is now the first of the binding's actions.
+fn commands_to_actions(table: &mut Table) -> Result<()> {
+    let Some(bindings) = table.get_mut("bindings") else {
+        return Ok(());
+    };
+    let bindings = bindings
+        .as_array_mut()
+        .ok_or_else(|| anyhow!("bindings is not a list"))?;
+    for binding in bindings {
+        let binding = binding
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("binding is not a table"))?;
+        let mut action = Table::new();
+        action.insert(
+            "type".to_owned(),
+            Value::String("toggle_command".to_owned()),
+        );
+        for key in ["command_name", "command_id"] {
+            if let Some(value) = binding.remove(key) {
+                action.insert(key.to_owned(), value);
+            }
+        }
+        if !action.contains_key("command_name") {
+            return Err(anyhow!("binding without command_name"));
+        }
+        binding.insert(
+            "actions".to_owned(),
+            Value::Array(vec![Value::Table(action)]),
+        );
+    }
+    Ok(())
+}
+
+/// Copies the config file next to itself before it gets rewritten, returning
+/// where to. Older configs held the secrets, so only the owner can read it.
+pub fn backup(config_file: &Path, suffix: &str) -> Result<PathBuf> {
+    let mut backup_file = config_file.as_os_str().to_owned();
+    backup_file.push(format!(".{}.bak", suffix));
+    let backup_file = PathBuf::from(backup_file);
+    let data = read(config_file)
+        .with_context(|| format!("Failed to read config file {:?} to back it up", config_file))?;
+    let mut file_handle = credentials::create_private(&backup_file)
+        .with_context(|| format!("Failed to back up config file to {:?}", backup_file))?;
+    file_handle.write_all(&data)?;
+    file_handle.flush()?;
+    Ok(backup_file)
+}
+
+/// Whether the file parses as a config of a known version.
+pub fn is_readable(config_file: &Path) -> bool {
+    let Ok(data) = read_to_string(config_file) else {
+        return false;
+    };
+    let Ok(mut value) = toml::from_str::<Value>(&data) else {
+        return false;
+    };
+    migrate(&mut value).is_ok() && value.try_into::<crate::Config>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::ActionConfig;
+    use crate::{CommandRef, Config};
+
+    const V1: &str = r#"
+client_id = "id"
+streamer = "chobo"
+user_access_token = "token"
+jwt = "jwt"
+reward_title = "5 Minute Fishing Trip"
+command_name = "fishinge"
+command_id = "c1"
+"#;
+
+    #[test]
+    fn migrates_v1_to_current() {
+        let mut value: Value = toml::from_str(V1).unwrap();
+        assert_eq!(migrate(&mut value).unwrap(), 1);
+        let config: Config = value.clone().try_into().unwrap();
+        assert_eq!(config.version, CURRENT_VERSION);
+        assert_eq!(config.user_access_token(), "token");
+        assert_eq!(config.bindings.len(), 1);
+        let binding = &config.bindings[0];
+        assert_eq!(binding.reward_title, "5 Minute Fishing Trip");
+        assert_eq!(binding.duration_secs, 300);
+        assert_eq!(
+            binding.actions,
+            [ActionConfig::ToggleCommand(CommandRef {
+                command_name: "fishinge".to_owned(),
+                command_id: "c1".to_owned(),
+            })]
+        );
+
+        assert_eq!(migrate(&mut value).unwrap(), CURRENT_VERSION);
+    }
+
+    #[test]
+    fn refuses_newer_and_broken_configs() {
+        let mut newer: Value = toml::from_str("version = 99").unwrap();
+        assert!(migrate(&mut newer).is_err());
+        let mut broken: Value = toml::from_str("client_id = \"id\"").unwrap();
+        assert!(migrate(&mut broken).is_err());
+    }
+}
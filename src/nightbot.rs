@@ -0,0 +1,337 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, rename, write, DirBuilder};
+
+use crate::chatbot::{Bot, BotCommand, ChatBot};
+use crate::metrics::Tracked;
+use crate::profile;
+use crate::{CommandRef, Config};
+
+const NIGHTBOT_API: &str = "https://api.nightbot.tv/1/";
+
+/// Overrides the API base URL, e.g. to try fishinge against a local mock server.
+pub const API_VAR: &str = "FISHINGE_NIGHTBOT_API";
+
+/// Nightbot commands cannot be switched off, so disabled commands are
+/// restricted to the channel owner instead.
+const DISABLED_LEVEL: &str = "owner";
+/// For commands that were disabled before fishinge first saw them.
+const ENABLED_LEVEL: &str = "everyone";
+
+/// The user levels commands had before they were disabled, by command ID,
+/// so enabling them puts those back.
+pub const LEVELS_FILE: &str = "nightbot_levels.json";
+
+type Levels = BTreeMap<String, String>;
+
+fn load_levels(profile: &str) -> Result<Levels> {
+    let path = profile::dir(profile)?.join(LEVELS_FILE);
+    if !path.exists() {
+        return Ok(Levels::new());
+    }
+    let data = read_to_string(&path).with_context(|| format!("Failed to read {:?}", &path))?;
+    serde_json::from_str(&data).with_context(|| format!("Failed to parse {:?}", &path))
+}
+
+/// Writes to a temporary file first, like the snapshots.
+fn save_levels(profile: &str, levels: &Levels) -> Result<()> {
+    let dir = profile::dir(profile)?;
+    DirBuilder::new().recursive(true).create(&dir)?;
+    let path = dir.join(LEVELS_FILE);
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+    write(&temporary, serde_json::to_string_pretty(levels)?)
+        .and_then(|_| rename(&temporary, &path))
+        .with_context(|| format!("Failed to write {:?}", &path))
+}
+
+fn api() -> String {
+    std::env::var(API_VAR).unwrap_or_else(|_| NIGHTBOT_API.to_owned())
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[allow(non_snake_case)]
+pub struct NightbotCommand {
+    pub _id: String,
+    pub name: String,
+    pub message: String,
+    pub coolDown: i32,
+    pub userLevel: String,
+    #[serde(default)]
+    pub count: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct CommandsResponse {
+    commands: Vec<NightbotCommand>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CommandResponse {
+    command: NightbotCommand,
+}
+
+/// The fields Nightbot lets us change.
+#[derive(Serialize, Debug)]
+#[allow(non_snake_case)]
+struct CommandUpdate<'a> {
+    message: &'a str,
+    coolDown: i32,
+    userLevel: &'a str,
+}
+
+/// Client for the custom commands of the channel the OAuth token belongs to.
+pub struct Nightbot {
+    client: reqwest::Client,
+    /// The API base URL, with a trailing slash.
+    api: String,
+    token: String,
+    /// Where the levels of disabled commands are kept.
+    profile: String,
+}
+
+impl Nightbot {
+    /// Lists the commands once, which checks the token.
+    pub async fn connect(config: &Config) -> Result<Nightbot> {
+        Nightbot::connect_to(&api(), config).await
+    }
+
+    /// Like [`Nightbot::connect`], with the API at another base URL.
+    pub async fn connect_to(api: &str, config: &Config) -> Result<Nightbot> {
+        let nightbot = Nightbot {
+            client: reqwest::Client::new(),
+            api: api.to_owned(),
+            token: config.nightbot_token().to_owned(),
+            profile: config.profile.clone(),
+        };
+        nightbot
+            .commands()
+            .await
+            .context("Nightbot rejected the token")?;
+        Ok(nightbot)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}commands{}", self.api, path)
+    }
+
+    pub async fn commands(&self) -> Result<Vec<NightbotCommand>> {
+        Ok(self
+            .client
+            .get(self.url(""))
+            .header("Accept", "application/json")
+            .header("Authorization", format!("Bearer {}", self.token))
+            .send_tracked("nightbot")
+            .await
+            .context("Failed sending request to get command list")?
+            .error_for_status()?
+            .json::<CommandsResponse>()
+            .await
+            .context("Failed to parse response for command list request")?
+            .commands)
+    }
+
+    pub async fn command(&self, command: &CommandRef) -> Result<NightbotCommand> {
+        if !command.command_id.is_empty() {
+            return Ok(self
+                .client
+                .get(self.url(&format!("/{}", command.command_id)))
+                .header("Accept", "application/json")
+                .header("Authorization", format!("Bearer {}", self.token))
+                .send_tracked("nightbot")
+                .await
+                .context("Failed sending request to get command")?
+                .error_for_status()
+                .with_context(|| format!("command with id \"{}\" not found", command.command_id))?
+                .json::<CommandResponse>()
+                .await
+                .context("Failed to parse response for command request")?
+                .command);
+        }
+
+        // Nightbot keeps the prefix as part of the name.
+        for found in self.commands().await? {
+            if found.name.trim_start_matches('!') == command.command_name {
+                return Ok(found);
+            }
+        }
+
+        Err(anyhow!("command \"{}\" not found", command.command_name))
+    }
+
+    async fn update(&self, id: &str, update: &CommandUpdate<'_>) -> Result<NightbotCommand> {
+        Ok(self
+            .client
+            .put(self.url(&format!("/{}", id)))
+            .header("Accept", "application/json")
+            .header("Authorization", format!("Bearer {}", self.token))
+            .json(update)
+            .send_tracked("nightbot")
+            .await
+            .context("Failed sending request to update command")?
+            .error_for_status()
+            .context("Nightbot rejected the command update")?
+            .json::<CommandResponse>()
+            .await
+            .context("Failed to parse response for command update request")?
+            .command)
+    }
+
+    async fn set_level(&self, command: &NightbotCommand, level: &str) -> Result<BotCommand> {
+        let updated = self
+            .update(
+                &command._id,
+                &CommandUpdate {
+                    message: &command.message,
+                    coolDown: command.coolDown,
+                    userLevel: level,
+                },
+            )
+            .await?;
+        bot_command(&updated)
+    }
+}
+
+fn bot_command(command: &NightbotCommand) -> Result<BotCommand> {
+    Ok(BotCommand {
+        bot: Bot::Nightbot,
+        id: command._id.clone(),
+        name: command.name.trim_start_matches('!').to_owned(),
+        enabled: command.userLevel != DISABLED_LEVEL,
+        raw: serde_json::to_value(command)?,
+    })
+}
+
+#[async_trait]
+impl ChatBot for Nightbot {
+    fn bot(&self) -> Bot {
+        Bot::Nightbot
+    }
+
+    async fn find(&self, command: &CommandRef) -> Result<BotCommand> {
+        bot_command(&self.command(command).await?)
+    }
+
+    /// Puts back the level the command had before it was disabled. An
+    /// enabled command keeps its level.
+    async fn enable(&self, command: &CommandRef) -> Result<BotCommand> {
+        let command = self.command(command).await?;
+        if command.userLevel != DISABLED_LEVEL {
+            return bot_command(&command);
+        }
+        let mut levels = load_levels(&self.profile)?;
+        let level = levels
+            .get(&command._id)
+            .cloned()
+            .unwrap_or_else(|| ENABLED_LEVEL.to_owned());
+        let enabled = self.set_level(&command, &level).await?;
+        if levels.remove(&command._id).is_some() {
+            save_levels(&self.profile, &levels)?;
+        }
+        Ok(enabled)
+    }
+
+    /// Remembers the level of the command before restricting it to the owner.
+    async fn disable(&self, command: &CommandRef) -> Result<BotCommand> {
+        let command = self.command(command).await?;
+        if command.userLevel == DISABLED_LEVEL {
+            return bot_command(&command);
+        }
+        let mut levels = load_levels(&self.profile)?;
+        levels.insert(command._id.clone(), command.userLevel.clone());
+        save_levels(&self.profile, &levels)?;
+        self.set_level(&command, DISABLED_LEVEL).await
+    }
+
+    /// Puts back the message, cooldown and user level. The level of an
+    /// enabled command is not needed anymore once it is back.
+    async fn restore(&self, original: &BotCommand) -> Result<BotCommand> {
+        let original: NightbotCommand = serde_json::from_value(original.raw.clone())
+            .context("Snapshot is not a Nightbot command")?;
+        if original.userLevel != DISABLED_LEVEL {
+            let mut levels = load_levels(&self.profile)?;
+            if levels.remove(&original._id).is_some() {
+                save_levels(&self.profile, &levels)?;
+            }
+        }
+        let updated = self
+            .update(
+                &original._id,
+                &CommandUpdate {
+                    message: &original.message,
+                    coolDown: original.coolDown,
+                    userLevel: &original.userLevel,
+                },
+            )
+            .await?;
+        bot_command(&updated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Mock};
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn enabling_puts_back_the_user_level() {
+        let _root = testing::config_root();
+        let stored = Arc::new(Mutex::new(NightbotCommand {
+            _id: "c1".to_owned(),
+            name: "!fish".to_owned(),
+            message: "Gone fishing".to_owned(),
+            coolDown: 5,
+            userLevel: "moderator".to_owned(),
+            count: 0,
+        }));
+        let command = Arc::clone(&stored);
+        let mock = Mock::start(move |request| {
+            let mut command = command.lock().unwrap();
+            match (request.method.as_str(), request.url.as_str()) {
+                ("GET", "/commands") => (
+                    200,
+                    format!(
+                        r#"{{"commands":[{}]}}"#,
+                        serde_json::to_string(&*command).unwrap()
+                    ),
+                ),
+                ("GET", "/commands/c1") => (
+                    200,
+                    format!(
+                        r#"{{"command":{}}}"#,
+                        serde_json::to_string(&*command).unwrap()
+                    ),
+                ),
+                ("PUT", "/commands/c1") => {
+                    let update: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+                    command.userLevel = update["userLevel"].as_str().unwrap().to_owned();
+                    (
+                        200,
+                        format!(
+                            r#"{{"command":{}}}"#,
+                            serde_json::to_string(&*command).unwrap()
+                        ),
+                    )
+                }
+                _ => (404, "{}".to_owned()),
+            }
+        });
+        let mut config = Config::new_profile("nightbot").unwrap();
+        config.nightbot_token = "token".to_owned();
+
+        let nightbot = Nightbot::connect_to(&mock.url, &config).await.unwrap();
+        let fish = CommandRef::named("fish");
+        assert!(!nightbot.disable(&fish).await.unwrap().enabled);
+        assert_eq!(stored.lock().unwrap().userLevel, DISABLED_LEVEL);
+        assert!(nightbot.enable(&fish).await.unwrap().enabled);
+        assert_eq!(stored.lock().unwrap().userLevel, "moderator");
+        assert!(load_levels("nightbot").unwrap().is_empty());
+        assert!(mock
+            .requests()
+            .iter()
+            .all(|request| request.header("Authorization") == Some("Bearer token")));
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Method, Request, Response};
+
+use crate::history::Outcome;
+use crate::server::{self, Server, Stopping};
+use crate::RedemptionEvent;
+
+/// The page OBS shows as a browser source.
+const PAGE: &str = include_str!("overlay.html");
+
+/// How often idle event streams get a comment, which notices closed pages.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+/// An open window as the overlay shows it.
+#[derive(Serialize, Debug, Clone)]
+pub struct Window {
+    pub reward: String,
+    /// Who opened the window.
+    pub user: String,
+    pub input: String,
+    pub closes_at: DateTime<Utc>,
+}
+
+/// What the overlay page is told, as JSON in Server-Sent Events.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Update<'a> {
+    /// Sent first, with all windows open at the time.
+    State {
+        windows: Vec<&'a Window>,
+    },
+    Opened(&'a Window),
+    Extended(&'a Window),
+    Closed {
+        reward: &'a str,
+    },
+    Redeemed {
+        reward: &'a str,
+        user: &'a str,
+        outcome: &'a Outcome,
+    },
+}
+
+/// The state of a channel's windows, passed on to every open overlay page.
+#[derive(Default)]
+pub struct Overlay {
+    windows: Mutex<BTreeMap<String, Window>>,
+    pages: Mutex<Vec<Sender<String>>>,
+}
+
+fn closes_at(remaining: Duration) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::from_std(remaining).unwrap_or_else(|_| chrono::Duration::zero())
+}
+
+impl Overlay {
+    /// Drops the pages that went away.
+    fn publish(&self, update: &Update) {
+        let Ok(data) = serde_json::to_string(update) else {
+            return;
+        };
+        if let Ok(mut pages) = self.pages.lock() {
+            pages.retain(|page| page.send(data.clone()).is_ok());
+        }
+    }
+
+    fn subscribe(&self) -> Receiver<String> {
+        let (tx, rx) = mpsc::channel();
+        if let Ok(windows) = self.windows.lock() {
+            let state = Update::State {
+                windows: windows.values().collect(),
+            };
+            if let Ok(data) = serde_json::to_string(&state) {
+                let _ = tx.send(data);
+            }
+        }
+        if let Ok(mut pages) = self.pages.lock() {
+            pages.push(tx);
+        }
+        rx
+    }
+
+    pub fn opened(&self, event: &RedemptionEvent, remaining: Duration) {
+        let window = Window {
+            reward: event.reward.title.clone(),
+            user: event.user_name.clone(),
+            input: event.user_input.clone(),
+            closes_at: closes_at(remaining),
+        };
+        self.publish(&Update::Opened(&window));
+        if let Ok(mut windows) = self.windows.lock() {
+            windows.insert(window.reward.clone(), window);
+        }
+    }
+
+    /// Keeps who opened the window, only the time left changes.
+    pub fn extended(&self, reward: &str, remaining: Duration) {
+        let Ok(mut windows) = self.windows.lock() else {
+            return;
+        };
+        if let Some(window) = windows.get_mut(reward) {
+            window.closes_at = closes_at(remaining);
+            self.publish(&Update::Extended(window));
+        }
+    }
+
+    pub fn closed(&self, reward: &str) {
+        if let Ok(mut windows) = self.windows.lock() {
+            windows.remove(reward);
+        }
+        self.publish(&Update::Closed { reward });
+    }
+
+    pub fn redeemed(&self, event: &RedemptionEvent, outcome: &Outcome) {
+        self.publish(&Update::Redeemed {
+            reward: &event.reward.title,
+            user: &event.user_name,
+            outcome,
+        });
+    }
+}
+
+/// Serves the page and its updates until dropped.
+pub fn serve(port: u16, overlay: Arc<Overlay>, output: Arc<Mutex<String>>) -> Result<Server> {
+    Server::start("the overlay", port, move |request, stopping| {
+        match (request.method(), request.url()) {
+            (Method::Get, "/") => server::respond(
+                &output,
+                request,
+                Response::from_string(PAGE)
+                    .with_header(server::header("Content-Type", "text/html; charset=utf-8")),
+            ),
+            (Method::Get, "/events") => stream(request, overlay.subscribe(), Arc::clone(stopping)),
+            _ => server::respond(&output, request, server::error(404, "not found")),
+        }
+    })
+}
+
+/// Writes the updates as Server-Sent Events on a thread of its own, until
+/// the page goes away or the server stops.
+fn stream(request: Request, updates: Receiver<String>, stopping: Stopping) {
+    let mut writer = request.into_writer();
+    thread::spawn(move || {
+        let mut message =
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n"
+                .to_owned();
+        loop {
+            if stopping.load(Ordering::Relaxed)
+                || writer
+                    .write_all(message.as_bytes())
+                    .and_then(|_| writer.flush())
+                    .is_err()
+            {
+                return;
+            }
+            message = match updates.recv_timeout(PING_INTERVAL) {
+                Ok(data) => format!("data: {}\n\n", data),
+                Err(RecvTimeoutError::Timeout) => ": ping\n\n".to_owned(),
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+        }
+    });
+}
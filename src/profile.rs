@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{copy, read_dir, read_to_string, DirBuilder};
+use std::path::{Path, PathBuf};
+
+/// The profile living directly in the config directory, as before profiles existed.
+pub const DEFAULT: &str = "default";
+
+const PROFILES_DIR: &str = "profiles";
+
+#[cfg(test)]
+thread_local! {
+    /// The config directory of the test running on this thread, see
+    /// [`crate::testing::config_root`].
+    pub(crate) static TEST_ROOT: std::cell::RefCell<Option<PathBuf>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+pub fn root_dir() -> Result<PathBuf> {
+    #[cfg(test)]
+    if let Some(root) = TEST_ROOT.with(|root| root.borrow().clone()) {
+        return Ok(root);
+    }
+    let mut config_dir = dirs::config_dir().ok_or_else(|| anyhow!("could not find config dir"))?;
+    config_dir.push("fishinge");
+    Ok(config_dir)
+}
+
+/// Directory holding the config and credentials files of a profile.
+pub fn dir(profile: &str) -> Result<PathBuf> {
+    let mut dir = root_dir()?;
+    if profile != DEFAULT {
+        validate_name(profile)?;
+        dir.push(PROFILES_DIR);
+        dir.push(profile);
+    }
+    Ok(dir)
+}
+
+/// Profile names become directory names, so they are kept simple.
+pub fn validate_name(profile: &str) -> Result<()> {
+    if profile.is_empty() {
+        return Err(anyhow!("profile name must not be empty"));
+    }
+    if !profile
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(anyhow!(
+            "profile name \"{}\" may only contain letters, digits, '-' and '_'",
+            profile
+        ));
+    }
+    Ok(())
+}
+
+/// All profiles with a config file, the default one first.
+pub fn list() -> Result<Vec<String>> {
+    let mut profiles = vec![DEFAULT.to_owned()];
+    let mut profiles_dir = root_dir()?;
+    profiles_dir.push(PROFILES_DIR);
+    if !profiles_dir.exists() {
+        return Ok(profiles);
+    }
+    let mut named = Vec::new();
+    for entry in read_dir(&profiles_dir)
+        .with_context(|| format!("Failed to list profiles in {:?}", &profiles_dir))?
+    {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name != DEFAULT
+            && validate_name(&name).is_ok()
+            && entry.path().join(crate::CONFIG_FILE).exists()
+        {
+            named.push(name);
+        }
+    }
+    named.sort();
+    profiles.extend(named);
+    Ok(profiles)
+}
+
+/// The script files the bindings of a saved config refer to.
+fn scripts(config_file: &Path) -> Result<Vec<String>> {
+    let config: toml::Value = toml::from_str(&read_to_string(config_file)?)
+        .with_context(|| format!("Could not parse config file {:?}", config_file))?;
+    let bindings = config.get("bindings").and_then(toml::Value::as_array);
+    let mut scripts: Vec<String> = bindings
+        .into_iter()
+        .flatten()
+        .filter_map(|binding| binding.get("script")?.as_str())
+        .filter(|script| !script.is_empty())
+        .map(str::to_owned)
+        .collect();
+    scripts.sort();
+    scripts.dedup();
+    Ok(scripts)
+}
+
+/// Copies the config and credentials files of one profile to a new one,
+/// along with the scripts of its bindings.
+pub fn copy_profile(from: &str, to: &str) -> Result<()> {
+    let source = dir(from)?;
+    let target = dir(to)?;
+    if target.join(crate::CONFIG_FILE).exists() {
+        return Err(anyhow!("profile \"{}\" already exists", to));
+    }
+    if !source.join(crate::CONFIG_FILE).exists() {
+        return Err(anyhow!("profile \"{}\" has not been saved yet", from));
+    }
+    // Checked up front, so a missing script leaves no half copied profile.
+    let scripts = scripts(&source.join(crate::CONFIG_FILE))?;
+    for script in &scripts {
+        if !crate::script::path(from, script)?.exists() {
+            return Err(anyhow!(
+                "script \"{}\" of profile \"{}\" does not exist",
+                script,
+                from
+            ));
+        }
+    }
+    DirBuilder::new().recursive(true).create(&target)?;
+    for script in &scripts {
+        let copied = crate::script::path(to, script)?;
+        if let Some(parent) = copied.parent() {
+            DirBuilder::new().recursive(true).create(parent)?;
+        }
+        copy(crate::script::path(from, script)?, copied).with_context(|| {
+            format!("Failed to copy script {} from profile \"{}\"", script, from)
+        })?;
+    }
+    for file in [crate::CONFIG_FILE, crate::credentials::FILE_NAME] {
+        if source.join(file).exists() {
+            copy(source.join(file), target.join(file))
+                .with_context(|| format!("Failed to copy {} from profile \"{}\"", file, from))?;
+        }
+    }
+    Ok(())
+}
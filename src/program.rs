@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+
+use crate::action::{self, Action};
+use crate::write_output;
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// A program run directly, without a shell, so filled in values cannot
+/// inject further commands.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    /// See [`action::render`] for the placeholders. Empty ones are skipped.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Runs a program when the window opens and optionally another one when it
+/// closes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    #[serde(flatten)]
+    pub start: Invocation,
+    /// After which each program is killed.
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<Invocation>,
+}
+
+impl Default for Program {
+    fn default() -> Program {
+        Program {
+            start: Invocation::default(),
+            timeout_secs: default_timeout(),
+            end: None,
+        }
+    }
+}
+
+impl Program {
+    pub fn validate(&self) -> Result<()> {
+        if self.start.program.is_empty() {
+            return Err(anyhow!("no program set"));
+        }
+        if matches!(&self.end, Some(end) if end.program.is_empty()) {
+            return Err(anyhow!("no end program set"));
+        }
+        Ok(())
+    }
+}
+
+impl Invocation {
+    /// Fills in each argument on its own.
+    fn render(&self, context: &action::Context) -> Invocation {
+        Invocation {
+            program: self.program.clone(),
+            args: self
+                .args
+                .iter()
+                .filter(|arg| !arg.is_empty())
+                .map(|arg| context.render(arg))
+                .collect(),
+        }
+    }
+
+    fn spawn(&self) -> Result<Child> {
+        Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.program))
+    }
+}
+
+/// Reads a pipe to the end on a task of its own, so a chatty program cannot
+/// block on a full pipe while we wait for it.
+fn drain(pipe: Option<impl AsyncRead + Unpin + Send + 'static>) -> JoinHandle<String> {
+    tokio::spawn(async move {
+        let mut text = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut text).await;
+        }
+        text
+    })
+}
+
+/// Waits for the program, killing it after the timeout, and logs how it
+/// went along with what it printed.
+async fn wait(mut child: Child, program: &str, timeout: Duration, output: &Arc<Mutex<String>>) {
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+    let status = match tokio::time::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) => format!("exited with {}", status),
+        Ok(Err(err)) => format!("could not be waited for: {}", err),
+        Err(_) => {
+            let _ = child.kill().await;
+            format!("was killed after {} seconds", timeout.as_secs())
+        }
+    };
+
+    let mut text = format!("{} {}", program, status);
+    for (name, handle) in [("stdout", stdout), ("stderr", stderr)] {
+        let printed = handle.await.unwrap_or_default();
+        for line in printed.lines().filter(|line| !line.trim().is_empty()) {
+            text.push_str(&format!("\n  {}: {}", name, line));
+        }
+    }
+    write_output(output, &text);
+}
+
+/// A [`Program`] while its window is open.
+pub struct Running {
+    program: Program,
+    started: Option<JoinHandle<()>>,
+}
+
+impl Running {
+    pub fn new(program: Program) -> Running {
+        Running {
+            program,
+            started: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Action for Running {
+    fn name(&self) -> String {
+        self.program.start.program.clone()
+    }
+
+    /// Fails only if the program cannot be started. It then runs in the
+    /// background, so a slow program does not hold up the other actions.
+    async fn start(&mut self, context: &action::Context<'_>) -> Result<()> {
+        let child = self.program.start.render(context).spawn()?;
+        let program = self.program.start.program.clone();
+        let timeout = Duration::from_secs(self.program.timeout_secs);
+        let output = Arc::clone(context.output);
+        self.started = Some(tokio::spawn(async move {
+            wait(child, &program, timeout, &output).await
+        }));
+        Ok(())
+    }
+
+    /// Runs the end program once the start program is done, and waits for
+    /// it, so it still runs when the window closes on shutdown. Both are
+    /// killed after the timeout, which bounds how long closing takes.
+    async fn stop(&mut self, context: &action::Context<'_>) -> Result<()> {
+        let started = self.started.take();
+        let end = match &self.program.end {
+            Some(end) => end.render(context),
+            None => return Ok(()),
+        };
+        if let Some(started) = started {
+            let _ = started.await;
+        }
+        let child = end.spawn()?;
+        let timeout = Duration::from_secs(self.program.timeout_secs);
+        wait(child, &end.program, timeout, context.output).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,654 @@
+use anyhow::anyhow;
+use eventsub_websocket::types::TwitchMessage;
+use eventsub_websocket::{event_handler, get_default_url, CloseCode, CloseFrame};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+use crate::api;
+use crate::chatbot::Bots;
+use crate::error::{self, Error};
+use crate::metrics;
+use crate::overlay::{self, Overlay};
+use crate::scheduler::{Message, Scheduler};
+use crate::server::{self, Server};
+use crate::{
+    create_subscription, credentials, delete_subscription, get_ids, parse_redemption,
+    session_subscriptions, validate_token, write_output, Config, Subscription,
+};
+
+/// How often the listener checks whether the config changed on disk.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Twitch asks for user access tokens to be validated once an hour.
+const TOKEN_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Requests from the UI to a running channel.
+pub enum Control {
+    /// Reads the config files again, as when they change on disk.
+    Reload,
+    /// Switches to a config edited in the UI.
+    Apply(Box<Config>),
+    /// Subscribes to the redemptions again, e.g. when they stopped arriving.
+    Resubscribe,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    /// Asked to stop, closing open windows.
+    Stopping,
+    Stopped,
+    Failed(String),
+}
+
+/// Everything listening to one streamer: its own EventSub session and
+/// subscriptions, scheduler and chat bot clients, each on their own
+/// tasks so a failing channel cannot take down the others.
+///
+/// The UI talks to the tasks only through channels, so it never waits on
+/// the network.
+pub struct Channel {
+    pub name: String,
+    pub output: Arc<Mutex<String>>,
+    status: Status,
+    /// Tells how the listener ended, once it has.
+    listener: Option<oneshot::Receiver<Result<(), Error>>>,
+    stop: watch::Sender<bool>,
+    control: Option<UnboundedSender<Control>>,
+    /// The config the channel currently runs with, kept up to date by the listener.
+    config: Arc<Mutex<Option<Config>>>,
+}
+
+impl Channel {
+    /// Spawns the listener on the tokio runtime, so this has to be called
+    /// from within it.
+    pub fn start(mut config: Config) -> Channel {
+        let mut channel = Channel::stopped(&config.profile);
+        let output = Arc::clone(&channel.output);
+        if let Some(migrated) = config.migrated.take() {
+            write_output(&output, &migrated);
+        }
+        let stop = channel.stop.subscribe();
+        let (control_tx, control_rx) = mpsc::unbounded_channel();
+        let (done_tx, done_rx) = oneshot::channel();
+        channel.config = Arc::new(Mutex::new(Some(config.clone())));
+        let shared = Arc::clone(&channel.config);
+        let api_control = control_tx.clone();
+        tokio::spawn(async move {
+            let result = listen(config, shared, output, stop, control_rx, api_control).await;
+            let _ = done_tx.send(result);
+        });
+        channel.listener = Some(done_rx);
+        channel.control = Some(control_tx);
+        channel.status = Status::Running;
+        channel
+    }
+
+    /// A channel that could not even be started, e.g. because its profile
+    /// failed to load.
+    pub fn failed(name: &str, error: Error) -> Channel {
+        let mut channel = Channel::stopped(name);
+        channel.fail(error);
+        channel
+    }
+
+    fn stopped(name: &str) -> Channel {
+        Channel {
+            name: name.to_owned(),
+            output: Arc::new(Mutex::new(String::new())),
+            status: Status::Stopped,
+            listener: None,
+            stop: watch::channel(false).0,
+            control: None,
+            config: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn fail(&mut self, error: Error) {
+        error::report(&self.output, &error);
+        self.status = Status::Failed(format!("{:#}", error));
+    }
+
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// The config the channel runs with, none if it never started.
+    pub fn config(&self) -> Option<Config> {
+        self.config.lock().ok()?.clone()
+    }
+
+    /// Asks a running channel to reload or apply a config; failures show up
+    /// in its output.
+    pub fn send(&self, control: Control) {
+        match &self.control {
+            Some(sender) if self.status == Status::Running => {
+                if sender.send(control).is_err() {
+                    write_output(&self.output, "Channel is not listening.");
+                }
+            }
+            _ => {
+                write_output(&self.output, "Channel is not listening.");
+            }
+        }
+    }
+
+    /// Notices when the listener has ended and logs how.
+    pub fn poll(&mut self) {
+        let Some(mut listener) = self.listener.take() else {
+            return;
+        };
+        let result = match listener.try_recv() {
+            Err(TryRecvError::Empty) => {
+                self.listener = Some(listener);
+                return;
+            }
+            Ok(result) => Some(result),
+            // The listener task panicked before it could tell.
+            Err(TryRecvError::Closed) => None,
+        };
+        if *self.stop.borrow() {
+            write_output(&self.output, "Stopped listening.");
+            self.status = Status::Stopped;
+            return;
+        }
+        write_output(&self.output, "Listen handler closed!\n  CANNOT CONTINUE!");
+        match result {
+            Some(Ok(())) => {
+                write_output(&self.output, "Handler closed correctly");
+                self.status = Status::Stopped;
+            }
+            Some(Err(err)) => self.fail(err),
+            None => self.fail(Error::Internal("The listener panicked".to_owned())),
+        }
+    }
+
+    /// Stops listening without waiting, [`Channel::poll`] notices when it is done.
+    pub fn request_stop(&mut self) {
+        if self.status == Status::Running {
+            self.stop.send_replace(true);
+            self.status = Status::Stopping;
+        }
+    }
+
+    /// Stops listening and waits until all open windows are closed. Blocks
+    /// the calling thread, which must not be one of the runtime's.
+    pub fn stop(&mut self) {
+        self.stop.send_replace(true);
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.blocking_recv();
+            write_output(&self.output, "Stopped listening.");
+            self.status = Status::Stopped;
+        }
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// The EventSub client hands messages to a plain channel on a thread of its
+/// own, this passes them on to the listener task.
+fn forward(
+    name: &str,
+    messages: std::sync::mpsc::Receiver<TwitchMessage>,
+) -> std::io::Result<UnboundedReceiver<TwitchMessage>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    thread::Builder::new()
+        .name(format!("{}-eventsub", name))
+        .spawn(move || {
+            while let Ok(message) = messages.recv() {
+                if tx.send(message).is_err() {
+                    return;
+                }
+            }
+        })?;
+    Ok(rx)
+}
+
+async fn listen(
+    config: Config,
+    shared: Arc<Mutex<Option<Config>>>,
+    output: Arc<Mutex<String>>,
+    mut stop: watch::Receiver<bool>,
+    mut control: UnboundedReceiver<Control>,
+    api_control: UnboundedSender<Control>,
+) -> Result<(), Error> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let url = get_default_url().map_err(|err| Error::EventSub(anyhow!(err)))?;
+    // Connecting blocks, so it gets a thread instead of holding up other tasks.
+    let event_res = tokio::task::spawn_blocking(move || event_handler(url, tx))
+        .await
+        .map_err(|_| Error::Internal("Connecting to EventSub panicked".to_owned()))?
+        .map_err(|err| Error::EventSub(anyhow!(err)))?;
+    let mut messages = forward(&config.profile, rx)
+        .map_err(|err| Error::Internal(format!("Could not start EventSub forwarder: {}", err)))?;
+
+    let (scheduler_tx, scheduler_rx) = mpsc::unbounded_channel();
+    let overlay = Arc::new(Overlay::default());
+    let scheduler_overlay = Arc::clone(&overlay);
+    let scheduler_output = Arc::clone(&output);
+    let scheduler_config = config.clone();
+    let scheduler: JoinHandle<Result<(), Error>> = tokio::spawn(async move {
+        Scheduler::new(scheduler_config, scheduler_output, scheduler_overlay)
+            .await?
+            .run(scheduler_rx)
+            .await;
+        Ok(())
+    });
+
+    let mut listener = Listener {
+        modified: config.modified(),
+        config,
+        shared,
+        output: Arc::clone(&output),
+        scheduler_tx,
+        session_id: None,
+        subscriptions: Vec::new(),
+        overlay,
+        overlay_server: None,
+        api_control,
+        api_server: None,
+        api_token: String::new(),
+        token_checked_at: None,
+        welcome_count: 0,
+    };
+    listener.serve();
+    let result = listener
+        .handle_messages(&mut messages, &mut control, &mut stop)
+        .await;
+
+    // Lets the scheduler close all open windows before the channel counts as stopped.
+    drop(listener);
+    let scheduler_result = scheduler
+        .await
+        .unwrap_or_else(|_| Err(Error::Internal("The scheduler panicked".to_owned())));
+
+    let reason = if *stop.borrow() {
+        "Client stopped listening."
+    } else {
+        "Client encountered error."
+    };
+    if let Ok(mut session) = event_res.session.lock() {
+        if let Err(err) = session.socket.close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: reason.into(),
+        })) {
+            write_output(&output, &format!("Could not close connection: {}", err));
+        }
+    }
+    result.and(scheduler_result)
+}
+
+/// The EventSub side of a channel, owning the config it subscribed with.
+struct Listener {
+    config: Config,
+    shared: Arc<Mutex<Option<Config>>>,
+    output: Arc<Mutex<String>>,
+    scheduler_tx: UnboundedSender<Message>,
+    session_id: Option<String>,
+    /// Subscriptions created for the current session.
+    subscriptions: Vec<Subscription>,
+    /// Last seen change of the config files, to notice edits on disk.
+    modified: Option<SystemTime>,
+    overlay: Arc<Overlay>,
+    /// Serves the overlay while the config sets a port.
+    overlay_server: Option<Server>,
+    /// Lets the control API ask for what only the listener can do.
+    api_control: UnboundedSender<Control>,
+    api_server: Option<Server>,
+    /// The token the control API was started with.
+    api_token: String,
+    token_checked_at: Option<Instant>,
+    welcome_count: u32,
+}
+
+impl Listener {
+    /// Handles whatever comes first until asked to stop. A scheduler that
+    /// ended has failed, [`listen`] returns why.
+    async fn handle_messages(
+        &mut self,
+        messages: &mut UnboundedReceiver<TwitchMessage>,
+        control: &mut UnboundedReceiver<Control>,
+        stop: &mut watch::Receiver<bool>,
+    ) -> Result<(), Error> {
+        let scheduler = self.scheduler_tx.clone();
+        let mut config_poll = tokio::time::interval(CONFIG_POLL_INTERVAL);
+        loop {
+            if *stop.borrow() {
+                return Ok(());
+            }
+            tokio::select! {
+                changed = stop.changed() => {
+                    if changed.is_err() {
+                        return Ok(());
+                    }
+                }
+                _ = scheduler.closed() => return Ok(()),
+                Some(control) = control.recv() => self.handle_control(control).await,
+                message = messages.recv() => match message {
+                    Some(message) => self.handle_message(message).await?,
+                    None => {
+                        return Err(Error::EventSub(anyhow!("the connection to Twitch closed")));
+                    }
+                },
+                _ = config_poll.tick() => {
+                    self.watch_config().await;
+                    self.check_token().await;
+                }
+            }
+        }
+    }
+
+    async fn handle_message(&mut self, message: TwitchMessage) -> Result<(), Error> {
+        match message {
+            TwitchMessage::Notification(msg) => match parse_redemption(&msg.payload.event) {
+                // A closed scheduler is noticed by `handle_messages`.
+                Ok(event) => {
+                    let _ = self.scheduler_tx.send(Message::Redemption(event));
+                }
+                Err(err) => self.log(&format!("Ignoring notification: {:#}", err)),
+            },
+            TwitchMessage::Welcome(msg) => {
+                self.welcome_count += 1;
+                if self.welcome_count == 1 {
+                    self.log("Connected to Twitch!");
+                } else {
+                    self.log("Reconnected to Twitch!");
+                    metrics::reconnected(&self.config.profile);
+                }
+                let session_id = msg.payload.session.id.to_owned();
+                self.session_id = Some(session_id.clone());
+                // Twitch moves the subscriptions over to the session of a
+                // reconnect, so they are looked up rather than made again.
+                let found = match session_subscriptions(&self.config, &session_id).await {
+                    Ok(found) => Some(found),
+                    Err(err) => {
+                        self.report(&Error::twitch(err));
+                        None
+                    }
+                };
+                match found {
+                    Some(found) if !found.is_empty() => self.subscriptions = found,
+                    None if !self.subscriptions.is_empty() => self.log(
+                        "Could not look up the subscriptions of the new session, keeping the previous ones.",
+                    ),
+                    _ => {
+                        let (broadcaster_id, reward_ids) =
+                            get_ids(&self.config).await.map_err(Error::twitch)?;
+                        self.subscriptions = self
+                            .subscribe(&self.config, &session_id, broadcaster_id, reward_ids)
+                            .await?;
+                        self.log(
+                            "Subscribed to redemption notifications!\nWaiting for redemptions...",
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn log(&self, text: &str) {
+        write_output(&self.output, text);
+    }
+
+    fn report(&self, error: &Error) {
+        error::report(&self.output, error);
+    }
+
+    /// Starts, moves or stops the local servers to match the config.
+    fn serve(&mut self) {
+        let (overlay, output) = (Arc::clone(&self.overlay), Arc::clone(&self.output));
+        let port = self.config.overlay_port;
+        if let Some(message) = server::keep(&mut self.overlay_server, "the overlay", port, || {
+            overlay::serve(port, overlay, output)
+        }) {
+            self.log(&message);
+        }
+
+        // A changed token needs a restart, as the server keeps the old one.
+        let port = self.config.api_port;
+        if port != 0 && self.api_token != self.config.api_token {
+            self.api_server = None;
+        }
+        self.api_token = self.config.api_token.clone();
+        let (profile, token) = (self.config.profile.clone(), self.api_token.clone());
+        let (scheduler, control) = (self.scheduler_tx.clone(), self.api_control.clone());
+        let output = Arc::clone(&self.output);
+        if let Some(message) = server::keep(&mut self.api_server, "the control API", port, || {
+            api::serve(port, &profile, &token, scheduler, control, output)
+        }) {
+            self.log(&message);
+        }
+    }
+
+    async fn handle_control(&mut self, control: Control) {
+        match control {
+            Control::Reload => {
+                self.modified = self.config.modified();
+                self.reload_from_disk().await;
+            }
+            Control::Apply(config) => {
+                // Only configs that work end up on disk.
+                if self.apply(*config).await {
+                    let config = self.config.clone();
+                    let written = tokio::task::spawn_blocking(move || config.write())
+                        .await
+                        .unwrap_or_else(|_| Err(anyhow!("writing the config panicked")));
+                    if let Err(err) = written {
+                        self.report(&Error::Storage(err.context("Could not save config")));
+                    }
+                    self.modified = self.config.modified();
+                }
+            }
+            Control::Resubscribe => match self.resubscribe().await {
+                Ok(()) => self.log("Resubscribed to redemption notifications."),
+                Err(err) => {
+                    self.report(&err);
+                    self.log("Could not resubscribe.");
+                }
+            },
+        }
+    }
+
+    /// Validates the token when due, which also keeps its expiry in the
+    /// metrics up to date.
+    async fn check_token(&mut self) {
+        if matches!(self.token_checked_at, Some(at) if at.elapsed() < TOKEN_CHECK_INTERVAL) {
+            return;
+        }
+        self.token_checked_at = Some(Instant::now());
+        if let Err(err) = validate_token(&reqwest::Client::new(), &self.config).await {
+            self.report(&Error::twitch(err));
+        }
+    }
+
+    async fn watch_config(&mut self) {
+        let modified = self.config.modified();
+        if modified != self.modified {
+            self.modified = modified;
+            self.log("Config file changed, reloading...");
+            self.reload_from_disk().await;
+        }
+    }
+
+    async fn reload_from_disk(&mut self) {
+        // Keep using the passphrase the running config was unlocked with.
+        let passphrase = Some(self.config.passphrase.clone())
+            .filter(|passphrase| !passphrase.is_empty())
+            .or_else(credentials::env_passphrase);
+        // Unlocking the credentials takes a while on purpose.
+        let profile = self.config.profile.clone();
+        let loaded = tokio::task::spawn_blocking(move || {
+            Config::load_with_passphrase(&profile, passphrase.as_deref())
+        })
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("loading the config panicked")));
+        match loaded {
+            Ok(config) => {
+                self.apply(config).await;
+            }
+            Err(err) => {
+                self.report(&Error::Config(err));
+                self.log("Config reload failed, keeping the previous config.");
+            }
+        }
+    }
+
+    /// Switches to a changed config, or reports why it cannot and keeps
+    /// running with the previous one.
+    async fn apply(&mut self, config: Config) -> bool {
+        if config == self.config {
+            // The scheduler compiles the scripts with the config, so an
+            // edited script needs it sent again.
+            if let Err(err) = self.scheduler_tx.send(Message::Reload(config)) {
+                self.report(&Error::Internal(format!(
+                    "Could not reload scheduler: {}",
+                    err
+                )));
+                return false;
+            }
+            return true;
+        }
+        if let Err(err) = self.check_and_resubscribe(&config).await {
+            self.report(&err);
+            self.log("Config reload failed, keeping the previous config.");
+            return false;
+        }
+        if let Err(err) = self.scheduler_tx.send(Message::Reload(config.clone())) {
+            self.report(&Error::Internal(format!(
+                "Could not reload scheduler: {}",
+                err
+            )));
+            return false;
+        }
+        if let Ok(mut shared) = self.shared.lock() {
+            *shared = Some(config.clone());
+        }
+        self.config = config;
+        self.serve();
+        self.log("Applied config changes.");
+        true
+    }
+
+    /// Everything that could fail is done before dropping subscriptions
+    /// no longer needed, so a bad config never leaves the channel deaf.
+    async fn check_and_resubscribe(&mut self, config: &Config) -> Result<(), Error> {
+        if config.credentials_locked {
+            return Err(Error::Config(anyhow!(
+                "the credentials are encrypted, set {} or apply them from the settings",
+                credentials::PASSPHRASE_VAR
+            )));
+        }
+        if config.bindings.is_empty() {
+            return Err(Error::Config(anyhow!("there are no bindings")));
+        }
+        if self.config.needs_reconnect(config) {
+            Bots::connect(config).await?;
+        }
+        if !self.config.needs_resubscribe(config) {
+            return Ok(());
+        }
+        let Some(session_id) = self.session_id.clone() else {
+            // Not connected yet, the welcome message subscribes with the new config.
+            return Ok(());
+        };
+
+        // Twitch refuses the same subscription twice, so the ones still
+        // needed are kept and only the missing ones are created.
+        let (broadcaster_id, reward_ids) = get_ids(config).await.map_err(Error::twitch)?;
+        let (mut kept, old): (Vec<_>, Vec<_>) =
+            self.subscriptions
+                .iter()
+                .cloned()
+                .partition(|subscription| {
+                    subscription.broadcaster_id == broadcaster_id
+                        && reward_ids.contains(&subscription.reward_id)
+                });
+        let missing: Vec<_> = reward_ids
+            .into_iter()
+            .filter(|reward_id| !kept.iter().any(|kept| kept.reward_id == *reward_id))
+            .collect();
+        if !missing.is_empty() {
+            kept.extend(
+                self.subscribe(config, &session_id, broadcaster_id, missing)
+                    .await?,
+            );
+        }
+        self.subscriptions = kept;
+        self.unsubscribe(&self.config, old).await;
+        self.log("Resubscribed with the new config.");
+        Ok(())
+    }
+
+    /// Replaces the subscriptions of the current session. The old ones go
+    /// first, as Twitch refuses the same subscription twice.
+    async fn resubscribe(&mut self) -> Result<(), Error> {
+        let Some(session_id) = self.session_id.clone() else {
+            return Err(Error::EventSub(anyhow!("not connected to Twitch yet")));
+        };
+        let (broadcaster_id, reward_ids) = get_ids(&self.config).await.map_err(Error::twitch)?;
+        let old_subscriptions = std::mem::take(&mut self.subscriptions);
+        self.unsubscribe(&self.config, old_subscriptions).await;
+        self.subscriptions = self
+            .subscribe(&self.config, &session_id, broadcaster_id, reward_ids)
+            .await?;
+        Ok(())
+    }
+
+    /// Subscribes to all rewards, undoing the subscriptions already made if
+    /// one of them fails.
+    async fn subscribe(
+        &self,
+        config: &Config,
+        session_id: &str,
+        broadcaster_id: String,
+        reward_ids: Vec<String>,
+    ) -> Result<Vec<Subscription>, Error> {
+        self.log(&format!(
+            "Found all required ids:\n Broadcaster:\n  {}\n Rewards:\n  {}",
+            broadcaster_id,
+            reward_ids.join("\n  ")
+        ));
+        let mut subscriptions = Vec::new();
+        for reward_id in reward_ids {
+            match create_subscription(
+                config,
+                session_id.to_owned(),
+                broadcaster_id.clone(),
+                reward_id.clone(),
+            )
+            .await
+            {
+                Ok(id) => subscriptions.push(Subscription {
+                    id,
+                    broadcaster_id: broadcaster_id.clone(),
+                    reward_id,
+                }),
+                Err(err) => {
+                    self.unsubscribe(config, subscriptions).await;
+                    return Err(Error::twitch(err));
+                }
+            }
+        }
+        Ok(subscriptions)
+    }
+
+    /// Deletes subscriptions made with the given config, logging failures.
+    async fn unsubscribe(&self, config: &Config, subscriptions: Vec<Subscription>) {
+        for subscription in subscriptions {
+            if let Err(err) = delete_subscription(config, &subscription.id).await {
+                self.report(&Error::twitch(err));
+            }
+        }
+    }
+}
@@ -0,0 +1,743 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, Utc};
+use rhai::AST;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::action::{self, Action, ActionConfig};
+use crate::chatbot::{Bot, Bots};
+use crate::error::{self, Error};
+use crate::gating::{Fallback, RECHECK_INTERVAL};
+use crate::history::{self, Entry, Outcome};
+use crate::limits::{self, Limits, MAX_COOLDOWN_SECS};
+use crate::metrics;
+use crate::overlay::Overlay;
+use crate::script::{self, Decision, Redemption};
+use crate::snapshot;
+use crate::{
+    format_duration, get_broadcaster_id, live_since, refund_redemption, write_output, Binding,
+    Config, RedemptionEvent, RedemptionReward,
+};
+
+pub enum Message {
+    Redemption(RedemptionEvent),
+    /// A changed config, already checked by the listener.
+    Reload(Config),
+    /// Opens or extends a binding's window without a redemption, for the
+    /// binding's duration unless set.
+    Enable {
+        reward_title: String,
+        duration: Option<Duration>,
+        reply: Sender<Result<Outcome>>,
+    },
+    /// Closes a binding's window early.
+    Disable {
+        reward_title: String,
+        reply: Sender<Result<()>>,
+    },
+    Status(Sender<State>),
+}
+
+/// The bindings and their open windows, for the control API.
+#[derive(Serialize, Debug, Clone)]
+pub struct State {
+    pub bindings: Vec<String>,
+    pub windows: Vec<WindowState>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WindowState {
+    pub reward_title: String,
+    /// Who redeemed the reward that opened the window.
+    pub opened_by: String,
+    pub remaining_secs: u64,
+}
+
+/// A binding's actions run until its window closes.
+struct Window {
+    binding: Binding,
+    /// The redemption that opened the window.
+    event: RedemptionEvent,
+    actions: Vec<Box<dyn Action>>,
+    /// Whether each action was reminded since the window last got extended.
+    reminded: Vec<bool>,
+    closes_at: Instant,
+}
+
+impl Window {
+    /// When the action's reminder is due, if it still is.
+    fn reminder_at(&self, index: usize) -> Option<Instant> {
+        if self.reminded[index] {
+            return None;
+        }
+        let lead = self.actions[index].reminder()?;
+        Some(self.closes_at.checked_sub(lead).unwrap_or(self.closes_at))
+    }
+
+    /// Reminds again after an extension if there is time for it, but not
+    /// when the window is too short to begin with.
+    fn reset_reminders(&mut self, now: Instant) {
+        for (action, reminded) in self.actions.iter().zip(self.reminded.iter_mut()) {
+            if let Some(lead) = action.reminder() {
+                *reminded = self.closes_at.saturating_duration_since(now) <= lead;
+            }
+        }
+    }
+}
+
+/// Time left until `closes_at`, rounded to whole seconds for the templates.
+fn remaining(closes_at: Instant) -> Duration {
+    let remaining = closes_at.saturating_duration_since(Instant::now());
+    Duration::from_secs((remaining.as_millis() as u64 + 500) / 1000)
+}
+
+/// Reads and compiles the bindings' scripts on a blocking thread. Scripts
+/// that fail are reported and fail the redemptions of their bindings.
+async fn compile_scripts(
+    config: &Config,
+    output: &Arc<Mutex<String>>,
+) -> HashMap<String, std::result::Result<AST, String>> {
+    let profile = config.profile.clone();
+    let replies = config.uses(Bot::StreamElements);
+    let files: Vec<String> = config
+        .bindings
+        .iter()
+        .map(|binding| binding.script.clone())
+        .filter(|file| !file.is_empty())
+        .collect();
+    let compiled = tokio::task::spawn_blocking(move || {
+        files
+            .into_iter()
+            .map(|file| {
+                let ast = script::compile(&profile, &file, replies);
+                (file, ast)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+    compiled
+        .into_iter()
+        .map(|(file, ast)| {
+            let ast = ast.map_err(|err| {
+                let message = format!("{:#}", err);
+                error::report(output, &Error::Script(err));
+                message
+            });
+            (file, ast)
+        })
+        .collect()
+}
+
+/// Opens and closes the binding windows of one channel.
+///
+/// Instead of sleeping through a window, the scheduler waits for the next
+/// message only until the earliest window has to close, so redemptions of
+/// other bindings are handled while a window is open.
+pub struct Scheduler {
+    config: Config,
+    output: Arc<Mutex<String>>,
+    overlay: Arc<Overlay>,
+    bots: Bots,
+    /// Open windows, keyed by reward title.
+    windows: HashMap<String, Window>,
+    /// Recently accepted redemptions, oldest first, to check limits against.
+    accepted: Vec<Entry>,
+    /// Redemptions waiting for their binding to open.
+    deferred: Vec<RedemptionEvent>,
+    recheck_at: Instant,
+    /// When the stream started, if it is live, and when that was looked up.
+    live: Option<(Option<DateTime<Utc>>, Instant)>,
+    /// The streamer's user id, looked up once the control API needs it.
+    broadcaster_id: Option<String>,
+    /// The bindings' scripts, compiled with the config, or why they did not.
+    scripts: HashMap<String, std::result::Result<AST, String>>,
+}
+
+impl Scheduler {
+    pub async fn new(
+        config: Config,
+        output: Arc<Mutex<String>>,
+        overlay: Arc<Overlay>,
+    ) -> std::result::Result<Scheduler, Error> {
+        let bots = Bots::connect(&config).await?;
+        for message in snapshot::restore_all(&bots, &config.profile).await {
+            write_output(&output, &message);
+        }
+        // Limits carry over a restart during the same stream.
+        let mut accepted: Vec<Entry> = history::load(&config.profile)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|entry| entry.outcome.is_accepted())
+            .collect();
+        accepted.sort_by_key(|entry| entry.redeemed_at);
+        let scripts = compile_scripts(&config, &output).await;
+        let mut scheduler = Scheduler {
+            config,
+            output,
+            overlay,
+            bots,
+            windows: HashMap::new(),
+            accepted,
+            deferred: Vec::new(),
+            recheck_at: Instant::now(),
+            live: None,
+            broadcaster_id: None,
+            scripts,
+        };
+        scheduler.forget_old();
+        Ok(scheduler)
+    }
+
+    /// Drops accepted redemptions no limit can refer to anymore.
+    fn forget_old(&mut self) {
+        let now = Utc::now();
+        let max_cooldown = chrono::Duration::seconds(MAX_COOLDOWN_SECS as i64);
+        // Without looking it up again, the last known start keeps at least
+        // the current stream.
+        let live_since = self.live.and_then(|(live_since, _)| live_since);
+        let stream = limits::current_stream(&self.accepted, now, live_since).len();
+        let recent = self
+            .accepted
+            .iter()
+            .rev()
+            .take_while(|entry| now - entry.redeemed_at <= max_cooldown)
+            .count();
+        let forget = self.accepted.len() - stream.max(recent);
+        self.accepted.drain(..forget);
+    }
+
+    /// Handles messages until the sender is dropped, then closes all windows.
+    pub async fn run(mut self, mut rx: UnboundedReceiver<Message>) {
+        loop {
+            let message = match self.next_wakeup() {
+                Some(wakeup) => match tokio::time::timeout_at(wakeup.into(), rx.recv()).await {
+                    Ok(message) => message,
+                    Err(_) => {
+                        self.remind().await;
+                        self.close_expired().await;
+                        if !self.deferred.is_empty() && self.recheck_at <= Instant::now() {
+                            self.retry_deferred().await;
+                        }
+                        continue;
+                    }
+                },
+                None => rx.recv().await,
+            };
+            match message {
+                Some(Message::Redemption(event)) => self.redeem(&event).await,
+                Some(Message::Reload(config)) => self.reload(config).await,
+                Some(Message::Enable {
+                    reward_title,
+                    duration,
+                    reply,
+                }) => {
+                    let _ = reply.send(self.enable(&reward_title, duration).await);
+                }
+                Some(Message::Disable {
+                    reward_title,
+                    reply,
+                }) => {
+                    let _ = reply.send(self.disable(&reward_title).await);
+                }
+                Some(Message::Status(reply)) => {
+                    let _ = reply.send(self.state());
+                }
+                None => {
+                    self.close_all().await;
+                    self.drop_deferred().await;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn next_wakeup(&self) -> Option<Instant> {
+        let recheck = (!self.deferred.is_empty()).then_some(self.recheck_at);
+        let reminders = self.windows.values().flat_map(|window| {
+            (0..window.actions.len()).filter_map(|index| window.reminder_at(index))
+        });
+        self.windows
+            .values()
+            .map(|window| window.closes_at)
+            .chain(reminders)
+            .chain(recheck)
+            .min()
+    }
+
+    fn log(&self, text: &str) {
+        write_output(&self.output, text);
+    }
+
+    /// Open windows keep the actions they were opened with, so they undo
+    /// what they did even if the binding changed meanwhile. Only
+    /// different chat bot logins close them early, while the old clients
+    /// can still reach them.
+    async fn reload(&mut self, config: Config) {
+        if self.config.needs_reconnect(&config) {
+            match Bots::connect(&config).await {
+                Ok(bots) => {
+                    self.close_all().await;
+                    self.bots = bots;
+                }
+                Err(err) => {
+                    error::report(&self.output, &err);
+                    self.log("Could not reconnect to the chat bots, keeping the previous config.");
+                    return;
+                }
+            }
+        }
+        self.scripts = compile_scripts(&config, &self.output).await;
+        if config.streamer() != self.config.streamer() {
+            self.broadcaster_id = None;
+        }
+        self.config = config;
+    }
+
+    async fn redeem(&mut self, event: &RedemptionEvent) {
+        let Some((outcome, duration)) = self.open(event).await else {
+            return;
+        };
+        self.overlay.redeemed(event, &outcome);
+        metrics::redeemed(&self.config.profile, &event.reward.title, outcome.kind());
+        let entry = Entry::new(event, outcome, duration);
+        if let Err(err) = history::append(&self.config.profile, &entry) {
+            error::report(
+                &self.output,
+                &Error::Storage(err.context("Could not record redemption")),
+            );
+        }
+        if entry.outcome.is_accepted() {
+            self.accepted.push(entry);
+            self.forget_old();
+        }
+    }
+
+    /// Logs why a redemption breaks a limit and refunds it if the binding says so.
+    async fn reject(&self, event: &RedemptionEvent, reason: String, refund: bool) -> Outcome {
+        self.log(&format!(
+            "Rejected \"{}\" by {}: {}",
+            event.reward.title, event.user_name, reason
+        ));
+        let mut refunded = false;
+        if refund {
+            match refund_redemption(&self.config, event).await {
+                Ok(_) => {
+                    self.log(&format!("Refunded {} points", event.reward.cost));
+                    refunded = true;
+                }
+                Err(err) => error::report(
+                    &self.output,
+                    &Error::twitch(err.context("Could not refund")),
+                ),
+            }
+        }
+        Outcome::Rejected { reason, refunded }
+    }
+
+    /// Why the binding does not take redemptions right now, if it does not.
+    async fn closed_reason(&mut self, binding: &Binding) -> Option<String> {
+        if binding.gate.is_always_open() {
+            return None;
+        }
+        binding
+            .gate
+            .closed_reason(Local::now(), self.is_live())
+            .await
+    }
+
+    async fn is_live(&mut self) -> Result<bool> {
+        Ok(self.live_since().await?.is_some())
+    }
+
+    async fn live_since(&mut self) -> Result<Option<DateTime<Utc>>> {
+        if let Some((live_since, checked_at)) = self.live {
+            if checked_at.elapsed() < RECHECK_INTERVAL {
+                return Ok(live_since);
+            }
+        }
+        let live_since = live_since(&self.config).await?;
+        self.live = Some((live_since, Instant::now()));
+        Ok(live_since)
+    }
+
+    /// When the stream started, for per stream limits. Without it they fall
+    /// back to the gaps between redemptions.
+    async fn stream_start(&mut self, limits: &Limits) -> Option<DateTime<Utc>> {
+        if limits.max_per_user_per_stream == 0 {
+            return None;
+        }
+        match self.live_since().await {
+            Ok(live_since) => live_since,
+            Err(err) => {
+                error::report(
+                    &self.output,
+                    &Error::twitch(err.context("Could not look up when the stream started")),
+                );
+                None
+            }
+        }
+    }
+
+    async fn retry_deferred(&mut self) {
+        self.recheck_at = Instant::now() + RECHECK_INTERVAL;
+        for event in std::mem::take(&mut self.deferred) {
+            let closed = match self.config.binding_for(&event).cloned() {
+                Some(binding) => self.closed_reason(&binding).await.is_some(),
+                None => false,
+            };
+            if closed {
+                self.deferred.push(event);
+            } else {
+                self.log(&format!(
+                    "Handling deferred redemption of {}",
+                    event.user_name
+                ));
+                self.redeem(&event).await;
+            }
+        }
+    }
+
+    async fn drop_deferred(&mut self) {
+        for event in std::mem::take(&mut self.deferred) {
+            let outcome = self
+                .reject(
+                    &event,
+                    "still deferred when listening stopped".to_owned(),
+                    false,
+                )
+                .await;
+            if let Err(err) = history::append(
+                &self.config.profile,
+                &Entry::new(&event, outcome, Duration::ZERO),
+            ) {
+                error::report(
+                    &self.output,
+                    &Error::Storage(err.context("Could not record redemption")),
+                );
+            }
+        }
+    }
+
+    /// Opens or extends the window of the redeemed binding. Returns nothing
+    /// if the redemption was deferred.
+    async fn open(&mut self, event: &RedemptionEvent) -> Option<(Outcome, Duration)> {
+        let Some(mut binding) = self.config.binding_for(event).cloned() else {
+            self.log(&format!("No binding for reward \"{}\"", event.reward.title));
+            return Some((Outcome::Unbound, Duration::ZERO));
+        };
+        self.log(&format!(
+            "{} redeemed \"{}\"",
+            event.user_name, event.reward.title
+        ));
+
+        if let Some(reason) = self.closed_reason(&binding).await {
+            let outcome = match binding.gate.otherwise {
+                Fallback::Defer => {
+                    self.log(&format!(
+                        "Deferred until \"{}\" opens ({}), only kept while listening",
+                        binding.reward_title(),
+                        reason
+                    ));
+                    if self.deferred.is_empty() {
+                        self.recheck_at = Instant::now() + RECHECK_INTERVAL;
+                    }
+                    self.deferred.push(event.clone());
+                    return None;
+                }
+                Fallback::Refund => self.reject(event, reason, true).await,
+                Fallback::Reject => self.reject(event, reason, false).await,
+            };
+            return Some((outcome, Duration::ZERO));
+        }
+
+        let accepted: Vec<Entry> = self
+            .accepted
+            .iter()
+            .filter(|entry| entry.reward_title == binding.reward_title())
+            .cloned()
+            .collect();
+        let live_since = self.stream_start(&binding.limits).await;
+        if let Err(reason) = binding
+            .limits
+            .check(event, &accepted, Utc::now(), live_since)
+        {
+            return Some((
+                self.reject(event, reason, binding.limits.refund).await,
+                Duration::ZERO,
+            ));
+        }
+
+        if !binding.script.is_empty() {
+            let redemption = Redemption {
+                event: event.clone(),
+                duration: binding.duration(),
+                remaining: self
+                    .windows
+                    .get(binding.reward_title())
+                    .map(|window| remaining(window.closes_at)),
+            };
+            let decided = match self.scripts.get(&binding.script) {
+                Some(Ok(ast)) => script::decide(ast, &binding, redemption, &self.output),
+                Some(Err(err)) => Err(anyhow!("{}", err)),
+                None => Err(anyhow!("script {} was not compiled", binding.script)),
+            };
+            match decided {
+                Ok((Decision::Accept(duration), actions)) => {
+                    if let Some(duration) = duration {
+                        binding.duration_secs = duration.as_secs();
+                    }
+                    // Only a new window runs them, an open one keeps its actions.
+                    binding.actions.extend(actions);
+                }
+                Ok((Decision::Reject(reason), _)) => {
+                    return Some((self.reject(event, reason, false).await, Duration::ZERO));
+                }
+                Ok((Decision::Refund(reason), _)) => {
+                    return Some((self.reject(event, reason, true).await, Duration::ZERO));
+                }
+                Err(err) => {
+                    let outcome = Outcome::Failed(format!("{:#}", err));
+                    error::report(&self.output, &Error::Script(err));
+                    return Some((outcome, Duration::ZERO));
+                }
+            }
+        }
+
+        Some(self.open_window(binding, event).await)
+    }
+
+    /// Extends the binding's window if it is open, or starts its actions.
+    async fn open_window(
+        &mut self,
+        binding: Binding,
+        event: &RedemptionEvent,
+    ) -> (Outcome, Duration) {
+        if let Some(window) = self.windows.get_mut(binding.reward_title()) {
+            window.closes_at += binding.duration();
+            window.reset_reminders(Instant::now());
+            let remaining = remaining(window.closes_at);
+            self.overlay.extended(binding.reward_title(), remaining);
+            metrics::window_opened(
+                &self.config.profile,
+                binding.reward_title(),
+                window.closes_at,
+            );
+            self.log(&format!(
+                "Extended \"{}\" by {}, closing in {}",
+                binding.reward_title(),
+                format_duration(binding.duration()),
+                format_duration(remaining)
+            ));
+            return (Outcome::Extended, binding.duration());
+        }
+
+        let mut actions: Vec<Box<dyn Action>> = binding
+            .actions
+            .iter()
+            .map(|action| action.build())
+            .collect();
+        let context = action::Context {
+            config: &self.config,
+            binding: &binding,
+            event,
+            remaining: binding.duration(),
+            bots: &self.bots,
+            output: &self.output,
+        };
+        match action::start_all(&mut actions, &context).await {
+            Ok(()) => {
+                self.log(&format!(
+                    "Waiting {}...",
+                    format_duration(binding.duration())
+                ));
+                let duration = binding.duration();
+                let now = Instant::now();
+                let mut window = Window {
+                    closes_at: now + duration,
+                    event: event.clone(),
+                    reminded: vec![false; actions.len()],
+                    actions,
+                    binding,
+                };
+                window.reset_reminders(now);
+                self.overlay.opened(event, duration);
+                metrics::window_opened(
+                    &self.config.profile,
+                    window.binding.reward_title(),
+                    window.closes_at,
+                );
+                self.windows
+                    .insert(window.binding.reward_title().to_owned(), window);
+                (Outcome::Enabled, duration)
+            }
+            Err(err) => {
+                error::report(&self.output, &err);
+                self.log("Attempting to continue...");
+                (Outcome::Failed(format!("{:#}", err)), Duration::ZERO)
+            }
+        }
+    }
+
+    /// Skips the gate, limits and script, and is not recorded, as there is
+    /// no redemption to judge.
+    async fn enable(&mut self, reward_title: &str, duration: Option<Duration>) -> Result<Outcome> {
+        let mut binding = self
+            .config
+            .bindings
+            .iter()
+            .find(|binding| binding.reward_title() == reward_title)
+            .cloned()
+            .ok_or_else(|| anyhow!("no binding for reward \"{}\"", reward_title))?;
+        if let Some(duration) = duration {
+            binding.duration_secs = duration.as_secs();
+        }
+        // Announcements need to know the channel.
+        let announces = binding
+            .actions
+            .iter()
+            .any(|action| matches!(action, ActionConfig::Announce(_)));
+        let broadcaster_user_id = if announces {
+            self.broadcaster_id().await?
+        } else {
+            String::new()
+        };
+        let event = RedemptionEvent {
+            broadcaster_user_id,
+            broadcaster_user_login: self.config.streamer().to_owned(),
+            broadcaster_user_name: self.config.streamer().to_owned(),
+            user_name: "the control API".to_owned(),
+            reward: RedemptionReward {
+                title: binding.reward_title().to_owned(),
+                ..RedemptionReward::default()
+            },
+            ..RedemptionEvent::default()
+        };
+        self.log(&format!("Control API opens \"{}\"", binding.reward_title()));
+        match self.open_window(binding, &event).await {
+            (Outcome::Failed(reason), _) => Err(anyhow!(reason)),
+            (outcome, _) => Ok(outcome),
+        }
+    }
+
+    async fn broadcaster_id(&mut self) -> Result<String> {
+        if let Some(id) = &self.broadcaster_id {
+            return Ok(id.clone());
+        }
+        let id = get_broadcaster_id(&reqwest::Client::new(), &self.config)
+            .await
+            .context("Could not look up the broadcaster")?;
+        self.broadcaster_id = Some(id.clone());
+        Ok(id)
+    }
+
+    async fn disable(&mut self, reward_title: &str) -> Result<()> {
+        if !self.windows.contains_key(reward_title) {
+            return Err(anyhow!("\"{}\" is not open", reward_title));
+        }
+        self.log(&format!("Control API closes \"{}\"", reward_title));
+        self.close(reward_title).await;
+        Ok(())
+    }
+
+    fn state(&self) -> State {
+        State {
+            bindings: self
+                .config
+                .bindings
+                .iter()
+                .map(|binding| binding.reward_title().to_owned())
+                .collect(),
+            windows: self
+                .windows
+                .iter()
+                .map(|(title, window)| WindowState {
+                    reward_title: title.clone(),
+                    opened_by: window.event.user_name.clone(),
+                    remaining_secs: remaining(window.closes_at).as_secs(),
+                })
+                .collect(),
+        }
+    }
+
+    async fn close_expired(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .windows
+            .iter()
+            .filter(|(_, window)| window.closes_at <= now)
+            .map(|(title, _)| title.clone())
+            .collect();
+        for title in expired {
+            self.close(&title).await;
+        }
+    }
+
+    async fn close_all(&mut self) {
+        let titles: Vec<String> = self.windows.keys().cloned().collect();
+        for title in titles {
+            self.close(&title).await;
+        }
+    }
+
+    async fn close(&mut self, title: &str) {
+        let Some(mut window) = self.windows.remove(title) else {
+            return;
+        };
+        let context = action::Context {
+            config: &self.config,
+            binding: &window.binding,
+            event: &window.event,
+            remaining: Duration::ZERO,
+            bots: &self.bots,
+            output: &self.output,
+        };
+        action::stop_all(&mut window.actions, &context).await;
+        self.overlay.closed(title);
+        metrics::window_closed(&self.config.profile, title);
+    }
+
+    async fn remind(&mut self) {
+        let now = Instant::now();
+        for window in self.windows.values_mut() {
+            let due: Vec<usize> = (0..window.actions.len())
+                .filter(|index| matches!(window.reminder_at(*index), Some(at) if at <= now))
+                .collect();
+            if due.is_empty() {
+                continue;
+            }
+            let context = action::Context {
+                config: &self.config,
+                binding: &window.binding,
+                event: &window.event,
+                remaining: remaining(window.closes_at),
+                bots: &self.bots,
+                output: &self.output,
+            };
+            for index in due {
+                window.reminded[index] = true;
+                let result = window.actions[index].remind(&context).await;
+                metrics::action(
+                    &self.config.profile,
+                    &window.actions[index].name(),
+                    "remind",
+                    result.is_ok(),
+                );
+                if let Err(err) = result {
+                    error::report(
+                        &self.output,
+                        &Error::Action {
+                            name: window.actions[index].name(),
+                            phase: "remind",
+                            cause: err,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
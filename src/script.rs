@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Context, Result};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{ASTNode, Dynamic, Engine, Expr, Scope, Stmt, AST};
+use std::fs::read_to_string;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::action::ActionConfig;
+use crate::announce::Announcement;
+use crate::streamelements::{CommandRef, Reply};
+use crate::{profile, write_output, Binding, RedemptionEvent};
+
+/// Called with the redemption, see the README for what it can return.
+const ENTRY_POINT: &str = "on_redemption";
+
+/// Operations a script may run per redemption, so a stuck loop cannot hang
+/// the scheduler.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// Changes a StreamElements command, so only for profiles connected to it.
+const SET_REPLY: &str = "set_reply";
+
+/// What a script decided about a redemption.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Opens or extends the window, for the binding's duration unless set.
+    Accept(Option<Duration>),
+    Reject(String),
+    Refund(String),
+}
+
+/// The redemption as a script sees it.
+#[derive(Debug, Clone)]
+pub struct Redemption {
+    pub event: RedemptionEvent,
+    /// The binding's duration.
+    pub duration: Duration,
+    /// Time left if the binding's window is open already.
+    pub remaining: Option<Duration>,
+}
+
+/// Scripts are kept next to the config and may not point outside of it.
+pub fn path(profile: &str, file: &str) -> Result<PathBuf> {
+    let relative = Path::new(file);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!(
+            "script \"{}\" has to be a file in the profile's directory",
+            file
+        ));
+    }
+    Ok(profile::dir(profile)?.join(relative))
+}
+
+/// An engine without access to files, modules or `eval`, and with limits
+/// on how much a script can do.
+fn engine(log: impl Fn(&str) + Send + Sync + 'static) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_module_resolver(DummyModuleResolver::new());
+    engine.disable_symbol("eval");
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(10_000);
+    engine.set_max_array_size(1_000);
+    engine.set_max_map_size(1_000);
+    engine.on_print(log);
+    engine.on_debug(|_, _, _| {});
+
+    engine
+        .register_type_with_name::<Redemption>("Redemption")
+        .register_get("user", |r: &mut Redemption| r.event.user_name.clone())
+        .register_get("login", |r: &mut Redemption| r.event.user_login.clone())
+        .register_get("user_id", |r: &mut Redemption| r.event.user_id.clone())
+        .register_get("input", |r: &mut Redemption| r.event.user_input.clone())
+        .register_get("reward", |r: &mut Redemption| r.event.reward.title.clone())
+        .register_get("cost", |r: &mut Redemption| r.event.reward.cost as i64)
+        .register_get("duration", |r: &mut Redemption| r.duration.as_secs() as i64)
+        .register_get("open", |r: &mut Redemption| r.remaining.is_some())
+        .register_get("remaining", |r: &mut Redemption| {
+            r.remaining.unwrap_or_default().as_secs() as i64
+        });
+
+    engine
+        .register_type_with_name::<Decision>("Decision")
+        .register_fn("accept", || Decision::Accept(None))
+        // A window is open for at least a second.
+        .register_fn("accept", |secs: i64| {
+            Decision::Accept(Some(Duration::from_secs(secs.max(1) as u64)))
+        })
+        .register_fn("reject", |reason: &str| Decision::Reject(reason.to_owned()))
+        .register_fn("refund", |reason: &str| Decision::Refund(reason.to_owned()));
+    engine
+}
+
+/// Reads and compiles the script. The AST does not depend on the engine it
+/// was compiled with, so it can be kept and run many times. `replies` is
+/// whether the profile connects to StreamElements, which `set_reply` needs.
+pub fn compile(profile: &str, file: &str, replies: bool) -> Result<AST> {
+    let engine = engine(|_| {});
+    let path = path(profile, file)?;
+    let source =
+        read_to_string(&path).with_context(|| format!("Failed to read script {:?}", &path))?;
+    let ast = engine
+        .compile(source)
+        .map_err(|err| anyhow!("{}", err))
+        .with_context(|| format!("Failed to compile script {:?}", &path))?;
+    if !replies && calls(&ast, SET_REPLY) {
+        return Err(anyhow!(
+            "script {:?} calls {}, which needs a StreamElements binding in the profile",
+            &path,
+            SET_REPLY
+        ));
+    }
+    if !ast
+        .iter_functions()
+        .any(|function| function.name == ENTRY_POINT && function.params.len() == 1)
+    {
+        return Err(anyhow!(
+            "script {:?} has no function {}(event)",
+            &path,
+            ENTRY_POINT
+        ));
+    }
+    Ok(ast)
+}
+
+/// Whether the script calls the function anywhere, as a statement, in an
+/// expression or as a method.
+fn calls(ast: &AST, name: &str) -> bool {
+    // The walk stops once the callback returns false.
+    !ast.walk(&mut |path: &[ASTNode]| {
+        let call = match path.last() {
+            Some(ASTNode::Stmt(Stmt::FnCall(call, _))) => call,
+            Some(ASTNode::Expr(Expr::FnCall(call, _) | Expr::MethodCall(call, _))) => call,
+            _ => return true,
+        };
+        call.name != name
+    })
+}
+
+/// Checks that the script compiles, without running it.
+pub fn check(profile: &str, file: &str, replies: bool) -> Result<()> {
+    compile(profile, file, replies).map(|_| ())
+}
+
+/// Runs the binding's compiled script on a redemption. Besides the decision,
+/// returns the actions the script asked for, which run with the window it
+/// opens. What the script prints goes to the log.
+pub fn decide(
+    ast: &AST,
+    binding: &Binding,
+    redemption: Redemption,
+    output: &Arc<Mutex<String>>,
+) -> Result<(Decision, Vec<ActionConfig>)> {
+    let log_output = Arc::clone(output);
+    let file = binding.script.clone();
+    let mut engine = engine(move |text| {
+        let text = format!("[{}] {}", file, text);
+        write_output(&log_output, &text);
+    });
+
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let requested = Arc::clone(&actions);
+    engine.register_fn("enable_command", move |name: &str| {
+        if let Ok(mut requested) = requested.lock() {
+            requested.push(ActionConfig::ToggleCommand(CommandRef::named(name)));
+        }
+    });
+    let requested = Arc::clone(&actions);
+    engine.register_fn(SET_REPLY, move |name: &str, template: &str| {
+        if let Ok(mut requested) = requested.lock() {
+            requested.push(ActionConfig::StreamElementsReply(Reply {
+                command: CommandRef::named(name),
+                template: template.to_owned(),
+                ..Reply::default()
+            }));
+        }
+    });
+    let requested = Arc::clone(&actions);
+    engine.register_fn("announce", move |message: &str| {
+        if let Ok(mut requested) = requested.lock() {
+            requested.push(ActionConfig::Announce(Announcement {
+                on_enable: message.to_owned(),
+                on_reminder: String::new(),
+                on_disable: String::new(),
+                ..Announcement::default()
+            }));
+        }
+    });
+
+    let result: Dynamic = engine
+        .call_fn(&mut Scope::new(), ast, ENTRY_POINT, (redemption,))
+        .map_err(|err| anyhow!("{}", err))
+        .with_context(|| format!("Script {} failed", binding.script))?;
+
+    let decision = if result.is_unit() {
+        Decision::Accept(None)
+    } else {
+        let type_name = result.type_name();
+        result.try_cast::<Decision>().ok_or_else(|| {
+            anyhow!(
+                "script {} returned {} instead of accept(), reject() or refund()",
+                binding.script,
+                type_name
+            )
+        })?
+    };
+    let actions = actions
+        .lock()
+        .map(|mut actions| std::mem::take(&mut *actions))
+        .unwrap_or_default();
+    Ok((decision, actions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn compiled_scripts_decide_each_redemption() {
+        let _root = testing::config_root();
+        let dir = profile::dir("scripts").unwrap();
+        create_dir_all(&dir).unwrap();
+        write(
+            dir.join("pond.rhai"),
+            r#"fn on_redemption(event) {
+                if event.input.contains("http") {
+                    return refund("no links");
+                }
+                announce(`${event.user} fished`);
+                accept(event.duration * 2)
+            }"#,
+        )
+        .unwrap();
+        let ast = compile("scripts", "pond.rhai", false).unwrap();
+        let binding = Binding {
+            script: "pond.rhai".to_owned(),
+            ..Default::default()
+        };
+        let output = Arc::new(Mutex::new(String::new()));
+        let redemption = |input: &str| Redemption {
+            event: RedemptionEvent {
+                user_name: "Ann".to_owned(),
+                user_input: input.to_owned(),
+                ..Default::default()
+            },
+            duration: Duration::from_secs(60),
+            remaining: None,
+        };
+
+        let (decision, actions) = decide(&ast, &binding, redemption("hi"), &output).unwrap();
+        assert_eq!(decision, Decision::Accept(Some(Duration::from_secs(120))));
+        assert!(matches!(
+            &actions[..],
+            [ActionConfig::Announce(announcement)] if announcement.on_enable == "Ann fished"
+        ));
+        let (decision, actions) = decide(&ast, &binding, redemption("http://x"), &output).unwrap();
+        assert_eq!(decision, Decision::Refund("no links".to_owned()));
+        assert!(actions.is_empty());
+        assert!(check("scripts", "../pond.rhai", true).is_err());
+
+        write(
+            dir.join("reply.rhai"),
+            r#"fn on_redemption(event) { set_reply("fish", "caught"); accept(0) }"#,
+        )
+        .unwrap();
+        assert!(check("scripts", "reply.rhai", false).is_err());
+        let ast = compile("scripts", "reply.rhai", true).unwrap();
+        let (decision, actions) = decide(&ast, &binding, redemption(""), &output).unwrap();
+        assert_eq!(decision, Decision::Accept(Some(Duration::from_secs(1))));
+        assert!(matches!(
+            &actions[..],
+            [ActionConfig::StreamElementsReply(_)]
+        ));
+    }
+}
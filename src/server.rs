@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tiny_http::{Header, Request, Response};
+
+use crate::error::{self, Error};
+
+/// Set once the server stops, for requests still being answered, like
+/// event streams.
+pub type Stopping = Arc<AtomicBool>;
+
+/// Serves HTTP on localhost until dropped, each request on a thread of its
+/// own so a slow one does not hold up the others.
+pub struct Server {
+    pub port: u16,
+    server: Arc<tiny_http::Server>,
+    stop: Stopping,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// `name` is for error messages, like "the overlay".
+    pub fn start(
+        name: &str,
+        port: u16,
+        handler: impl Fn(Request, &Stopping) + Send + Sync + 'static,
+    ) -> Result<Server> {
+        let server = tiny_http::Server::http(("127.0.0.1", port))
+            .map_err(|err| anyhow!("Could not serve {} on port {}: {}", name, port, err))?;
+        let server = Arc::new(server);
+        let handler = Arc::new(handler);
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let server = Arc::clone(&server);
+            let stop = Arc::clone(&stop);
+            thread::Builder::new()
+                .name(format!("{} server", name))
+                .spawn(move || {
+                    for request in server.incoming_requests() {
+                        if stop.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        let handler = Arc::clone(&handler);
+                        let stop = Arc::clone(&stop);
+                        thread::spawn(move || handler(request, &stop));
+                    }
+                })?
+        };
+        Ok(Server {
+            port,
+            server,
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Drop for Server {
+    /// Requests still being answered finish on their own threads. The
+    /// accepting thread is joined on a blocking thread when on a runtime,
+    /// so it never holds up the worker.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.server.unblock();
+        let Some(handle) = self.handle.take() else {
+            return;
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn_blocking(move || handle.join());
+            }
+            Err(_) => {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// Starts, moves or stops a server to match the configured port, 0 being
+/// off, and tells how it went for the log.
+pub fn keep(
+    server: &mut Option<Server>,
+    name: &str,
+    port: u16,
+    start: impl FnOnce() -> Result<Server>,
+) -> Option<String> {
+    if matches!(server, Some(running) if running.port == port) {
+        return None;
+    }
+    let stopped = server.take().is_some();
+    if port == 0 {
+        return stopped.then(|| format!("Stopped serving {}", name));
+    }
+    match start() {
+        Ok(started) => {
+            *server = Some(started);
+            Some(format!("Serving {} on http://127.0.0.1:{}/", name, port))
+        }
+        Err(err) => Some(format!("{:#}", err)),
+    }
+}
+
+pub fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("header names and values are ASCII")
+}
+
+pub fn json(value: &impl Serialize) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_data(serde_json::to_vec(value).unwrap_or_default())
+        .with_header(header("Content-Type", "application/json"))
+}
+
+/// A JSON `{"error": ...}` with the status code.
+pub fn error(status: u16, message: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    json(&serde_json::json!({ "error": message })).with_status_code(status)
+}
+
+/// Answers, reporting it if the client went away meanwhile.
+pub fn respond<R: std::io::Read>(
+    output: &Arc<Mutex<String>>,
+    request: Request,
+    response: Response<R>,
+) {
+    if let Err(err) = request.respond(response) {
+        error::report(
+            output,
+            &Error::Serve(anyhow::Error::new(err).context("Could not answer local request")),
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{read_to_string, rename, write, DirBuilder};
+use std::path::PathBuf;
+
+use crate::chatbot::{BotCommand, Bots};
+use crate::profile;
+
+/// Commands as they were before an action changed them, so they can be put
+/// back after a crash.
+pub const FILE_NAME: &str = "snapshots.json";
+
+/// A command as it was before the first action still holding it changed it.
+///
+/// Several actions can change the same command, e.g. a toggle and a reply
+/// rewrite or two bindings, so the snapshot is only dropped once the last
+/// of them has restored it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    #[serde(flatten)]
+    pub command: BotCommand,
+    #[serde(default)]
+    pub holders: u32,
+}
+
+/// Keyed by bot and command ID.
+pub type Snapshots = BTreeMap<String, Snapshot>;
+
+fn key(command: &BotCommand) -> String {
+    format!("{}/{}", command.bot, command.id)
+}
+
+pub fn path(profile: &str) -> Result<PathBuf> {
+    Ok(profile::dir(profile)?.join(FILE_NAME))
+}
+
+pub fn load(profile: &str) -> Result<Snapshots> {
+    let path = path(profile)?;
+    if !path.exists() {
+        return Ok(Snapshots::new());
+    }
+    let data =
+        read_to_string(&path).with_context(|| format!("Failed to read snapshots {:?}", &path))?;
+    serde_json::from_str(&data).with_context(|| format!("Failed to parse snapshots {:?}", &path))
+}
+
+/// Writes to a temporary file first, so a crash leaves either the old or the
+/// new snapshots behind.
+fn save(profile: &str, snapshots: &Snapshots) -> Result<()> {
+    let path = path(profile)?;
+    if let Some(dir) = path.parent() {
+        DirBuilder::new().recursive(true).create(dir)?;
+    }
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+    write(&temporary, serde_json::to_string_pretty(snapshots)?)
+        .and_then(|_| rename(&temporary, &path))
+        .with_context(|| format!("Failed to write snapshots to {:?}", &path))
+}
+
+/// Remembers the command unless a snapshot of it is kept already, which
+/// then is older and closer to the original. Each call needs a matching
+/// [`forget`].
+pub fn keep(profile: &str, command: &BotCommand) -> Result<BotCommand> {
+    let mut snapshots = load(profile)?;
+    let snapshot = snapshots.entry(key(command)).or_insert_with(|| Snapshot {
+        command: command.clone(),
+        holders: 0,
+    });
+    snapshot.holders += 1;
+    let original = snapshot.command.clone();
+    save(profile, &snapshots)?;
+    Ok(original)
+}
+
+/// Lets go of the snapshot of the command, forgetting it once no action
+/// holds it anymore.
+pub fn forget(profile: &str, command: &BotCommand) -> Result<()> {
+    let mut snapshots = load(profile)?;
+    let key = key(command);
+    let Some(snapshot) = snapshots.get_mut(&key) else {
+        return Ok(());
+    };
+    snapshot.holders = snapshot.holders.saturating_sub(1);
+    if snapshot.holders == 0 {
+        snapshots.remove(&key);
+    }
+    save(profile, &snapshots)
+}
+
+/// Forgets the snapshot no matter who holds it.
+fn remove(profile: &str, command: &BotCommand) -> Result<()> {
+    let mut snapshots = load(profile)?;
+    if snapshots.remove(&key(command)).is_some() {
+        save(profile, &snapshots)?;
+    }
+    Ok(())
+}
+
+/// Restores commands a previous run changed but did not get to restore,
+/// returning what happened for the log.
+pub async fn restore_all(bots: &Bots, profile: &str) -> Vec<String> {
+    let snapshots = match load(profile) {
+        Ok(snapshots) => snapshots,
+        Err(err) => return vec![format!("{:#}", err)],
+    };
+    let mut messages = Vec::new();
+    for original in snapshots.into_values().map(|snapshot| snapshot.command) {
+        let restored = match bots.get(original.bot) {
+            Ok(bot) => bot.restore(&original).await,
+            Err(err) => Err(err),
+        };
+        messages.push(match restored.and_then(|_| remove(profile, &original)) {
+            Ok(()) => format!(
+                "Restored {} command !{} left changed by an earlier run",
+                original.bot, original.name
+            ),
+            Err(err) => format!("Could not restore !{}: {:#}", original.name, err),
+        });
+    }
+    messages
+}
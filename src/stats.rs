@@ -0,0 +1,266 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::write;
+use std::path::{Path, PathBuf};
+
+use crate::format_duration;
+use crate::history::Entry;
+
+/// Redemptions further apart than this belong to different streams, as the
+/// history does not know when a stream started or ended.
+pub const STREAM_GAP_HOURS: i64 = 4;
+
+const TOP_REDEEMERS: usize = 10;
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Summary {
+    pub redemptions: usize,
+    pub failures: usize,
+    pub points_spent: u64,
+    /// Total time granted by redemptions, including extensions.
+    pub enabled_secs: u64,
+    pub top_redeemers: Vec<Redeemer>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Redeemer {
+    pub user_name: String,
+    pub redemptions: usize,
+    pub points_spent: u64,
+}
+
+impl Summary {
+    pub fn of<'a>(entries: impl IntoIterator<Item = &'a Entry>) -> Summary {
+        let mut summary = Summary::default();
+        let mut redeemers: HashMap<&str, Redeemer> = HashMap::new();
+        for entry in entries {
+            summary.redemptions += 1;
+            if entry.outcome.is_failure() {
+                summary.failures += 1;
+            }
+            let cost = if entry.outcome.is_refunded() {
+                0
+            } else {
+                entry.cost
+            };
+            summary.points_spent += cost;
+            summary.enabled_secs += entry.duration_secs;
+            let redeemer = redeemers.entry(&entry.user_id).or_insert_with(|| Redeemer {
+                user_name: entry.user_name.clone(),
+                redemptions: 0,
+                points_spent: 0,
+            });
+            redeemer.redemptions += 1;
+            redeemer.points_spent += cost;
+        }
+        let mut redeemers: Vec<Redeemer> = redeemers.into_values().collect();
+        redeemers.sort_by(|a, b| {
+            b.redemptions
+                .cmp(&a.redemptions)
+                .then(b.points_spent.cmp(&a.points_spent))
+                .then(a.user_name.cmp(&b.user_name))
+        });
+        redeemers.truncate(TOP_REDEEMERS);
+        summary.top_redeemers = redeemers;
+        summary
+    }
+
+    pub fn failure_rate(&self) -> f64 {
+        if self.redemptions == 0 {
+            return 0.;
+        }
+        self.failures as f64 / self.redemptions as f64
+    }
+
+    pub fn enabled_minutes(&self) -> u64 {
+        self.enabled_secs / 60
+    }
+}
+
+/// A day or a stream.
+#[derive(Serialize, Debug, Clone)]
+pub struct Period {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub summary: Summary,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Report {
+    pub profile: String,
+    pub generated_at: DateTime<Utc>,
+    pub total: Summary,
+    pub days: Vec<Period>,
+    pub streams: Vec<Period>,
+}
+
+impl Report {
+    pub fn new(profile: &str, entries: &[Entry]) -> Report {
+        let mut entries = entries.to_vec();
+        entries.sort_by_key(|entry| entry.redeemed_at);
+        let entries = &entries[..];
+        Report {
+            profile: profile.to_owned(),
+            generated_at: Utc::now(),
+            total: Summary::of(entries),
+            days: group(entries, |previous, entry| {
+                previous.redeemed_at.date_naive() == entry.redeemed_at.date_naive()
+            }),
+            streams: group(entries, |previous, entry| {
+                entry.redeemed_at - previous.redeemed_at <= Duration::hours(STREAM_GAP_HOURS)
+            }),
+        }
+    }
+
+    /// A report on the latest stream only, for after it ended.
+    pub fn last_stream(profile: &str, entries: &[Entry]) -> Report {
+        let start = Report::new(profile, entries)
+            .streams
+            .last()
+            .map(|stream| stream.start);
+        let entries: Vec<Entry> = entries
+            .iter()
+            .filter(|entry| Some(entry.redeemed_at) >= start)
+            .cloned()
+            .collect();
+        Report::new(profile, &entries)
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!(
+            "# Redemptions of {}\n\nGenerated {}\n\n",
+            self.profile,
+            self.generated_at.format("%Y-%m-%d %H:%M UTC")
+        );
+        markdown += &summary_markdown(&self.total);
+        markdown += "\n## Streams\n\n";
+        markdown += &periods_markdown(&self.streams, "%Y-%m-%d %H:%M");
+        markdown += "\n## Days\n\n";
+        markdown += &periods_markdown(&self.days, "%Y-%m-%d");
+        markdown
+    }
+
+    /// Writes the report as `<name>.json` and `<name>.md` into the directory.
+    pub fn export(&self, dir: &Path, name: &str) -> Result<(PathBuf, PathBuf)> {
+        let json = dir.join(format!("{}.json", name));
+        let markdown = dir.join(format!("{}.md", name));
+        write(&json, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write report to {:?}", &json))?;
+        write(&markdown, self.to_markdown())
+            .with_context(|| format!("Failed to write report to {:?}", &markdown))?;
+        Ok((json, markdown))
+    }
+}
+
+/// Splits sorted entries into runs where each entry belongs with the one before.
+fn group(entries: &[Entry], together: impl Fn(&Entry, &Entry) -> bool) -> Vec<Period> {
+    let mut periods = Vec::new();
+    let mut start = 0;
+    for index in 1..=entries.len() {
+        if index == entries.len() || !together(&entries[index - 1], &entries[index]) {
+            let run = &entries[start..index];
+            periods.push(Period {
+                start: run[0].redeemed_at,
+                end: run[run.len() - 1].redeemed_at,
+                summary: Summary::of(run),
+            });
+            start = index;
+        }
+    }
+    periods
+}
+
+fn summary_markdown(summary: &Summary) -> String {
+    let mut markdown = format!(
+        "- Redemptions: {}\n- Points spent: {}\n- Enabled for: {}\n- Failures: {} ({:.1}%)\n",
+        summary.redemptions,
+        summary.points_spent,
+        format_duration(std::time::Duration::from_secs(
+            summary.enabled_minutes() * 60
+        )),
+        summary.failures,
+        summary.failure_rate() * 100.
+    );
+    if !summary.top_redeemers.is_empty() {
+        markdown += "\n| Top redeemer | Redemptions | Points |\n|---|---|---|\n";
+        for redeemer in &summary.top_redeemers {
+            markdown += &format!(
+                "| {} | {} | {} |\n",
+                redeemer.user_name, redeemer.redemptions, redeemer.points_spent
+            );
+        }
+    }
+    markdown
+}
+
+fn periods_markdown(periods: &[Period], format: &str) -> String {
+    let mut markdown =
+        "| Start | Redemptions | Points | Minutes enabled | Failure rate |\n|---|---|---|---|---|\n"
+            .to_owned();
+    for period in periods.iter().rev() {
+        markdown += &format!(
+            "| {} | {} | {} | {} | {:.1}% |\n",
+            period.start.format(format),
+            period.summary.redemptions,
+            period.summary.points_spent,
+            period.summary.enabled_minutes(),
+            period.summary.failure_rate() * 100.
+        );
+    }
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Outcome;
+    use crate::RedemptionEvent;
+
+    fn entry(user: &str, redeemed_at: &str, outcome: Outcome) -> Entry {
+        let event = RedemptionEvent {
+            user_id: user.to_owned(),
+            user_name: user.to_owned(),
+            redeemed_at: redeemed_at.to_owned(),
+            ..Default::default()
+        };
+        let mut entry = Entry::new(&event, outcome, std::time::Duration::from_secs(300));
+        entry.cost = 100;
+        entry
+    }
+
+    #[test]
+    fn groups_streams_by_gap_and_days_by_date() {
+        let entries = [
+            entry("ann", "2024-01-01T20:00:00Z", Outcome::Enabled),
+            entry(
+                "bob",
+                "2024-01-01T23:00:00Z",
+                Outcome::Failed("x".to_owned()),
+            ),
+            entry("ann", "2024-01-02T01:00:00Z", Outcome::Extended),
+            entry("ann", "2024-01-02T18:00:00Z", Outcome::Enabled),
+        ];
+        let report = Report::new("test", &entries);
+
+        let streams: Vec<usize> = report
+            .streams
+            .iter()
+            .map(|stream| stream.summary.redemptions)
+            .collect();
+        assert_eq!(streams, [3, 1]);
+        assert_eq!(report.streams[0].end, entries[2].redeemed_at);
+        assert_eq!(report.streams[0].summary.failures, 1);
+        assert_eq!(report.streams[0].summary.top_redeemers[0].user_name, "ann");
+
+        let days: Vec<usize> = report
+            .days
+            .iter()
+            .map(|day| day.summary.redemptions)
+            .collect();
+        assert_eq!(days, [2, 2]);
+        assert_eq!(report.total.points_spent, 400);
+        assert!(group(&[], |_, _| true).is_empty());
+    }
+}
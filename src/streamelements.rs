@@ -0,0 +1,418 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::action::{self, Action};
+use crate::chatbot::{Bot, BotCommand, ChatBot};
+use crate::metrics::Tracked;
+use crate::{snapshot, Config};
+
+const STREAMELEMENTS_API: &str = "https://api.streamelements.com/kappa/v2/";
+
+/// Overrides the API base URL, e.g. to try fishinge against a local mock server.
+pub const API_VAR: &str = "FISHINGE_STREAMELEMENTS_API";
+
+/// The API base URL, with a trailing slash.
+pub(crate) fn api() -> String {
+    std::env::var(API_VAR).unwrap_or_else(|_| STREAMELEMENTS_API.to_owned())
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(dead_code, non_snake_case)]
+pub(crate) struct AccessResponse {
+    channelId: String,
+    username: String,
+    avatar: String,
+    provider: String,
+    role: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[allow(dead_code, non_snake_case)]
+pub struct CommandResponse {
+    pub cooldown: Cooldown,
+    pub aliases: Vec<String>,
+    pub keywords: Vec<String>,
+    pub enabled: bool,
+    pub enabledOnline: bool,
+    pub enabledOffline: bool,
+    pub hidden: bool,
+    pub cost: i32,
+    pub r#type: String,
+    pub accessLevel: i32,
+    pub _id: String,
+    pub regex: Option<String>,
+    pub reply: String,
+    pub command: String,
+    pub channel: String,
+    pub createdAt: String,
+    pub updatedAt: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[allow(dead_code)]
+pub struct Cooldown {
+    pub user: i32,
+    pub global: i32,
+}
+
+pub(crate) async fn get_accesses(
+    client: &reqwest::Client,
+    api: &str,
+    config: &Config,
+) -> Result<Vec<AccessResponse>> {
+    client
+        .get(format!("{}users/access", api))
+        .header("Content-Type", "application/json")
+        .header("Accept", "application/json")
+        .header("Authorization", format!("Bearer {}", config.jwt()))
+        .send_tracked("streamelements")
+        .await
+        .context("Failed sending request to update list of users")?
+        .error_for_status()
+        .context("StreamElements rejected the JWT")?
+        .json::<Vec<AccessResponse>>()
+        .await
+        .context("Failed to parse response for user list request")
+}
+
+pub(crate) fn find_channel_id(accesses: Vec<AccessResponse>, config: &Config) -> Result<String> {
+    for res in accesses {
+        if res.username == config.streamer().to_lowercase() {
+            return Ok(res.channelId);
+        }
+    }
+
+    Err(anyhow!("channel_id not found"))
+}
+
+/// A bot command, by name and, once picked from the list, by ID.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandRef {
+    pub command_name: String,
+    #[serde(default)]
+    pub command_id: String,
+}
+
+impl CommandRef {
+    pub fn named(command_name: &str) -> CommandRef {
+        CommandRef {
+            command_name: command_name.to_owned(),
+            command_id: String::new(),
+        }
+    }
+}
+
+/// A reply to show while the window is open, with optional cooldowns and cost.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reply {
+    #[serde(flatten)]
+    pub command: CommandRef,
+    /// See [`action::render`] for the placeholders.
+    pub template: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_cooldown_secs: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub global_cooldown_secs: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<i32>,
+}
+
+/// Lists all bot commands of the configured StreamElements channel.
+pub async fn list_commands(config: &Config) -> Result<Vec<CommandResponse>> {
+    StreamElements::connect(config).await?.commands().await
+}
+
+/// Client for the bot commands of a single StreamElements channel.
+pub struct StreamElements {
+    client: reqwest::Client,
+    /// The API base URL, see [`api`].
+    api: String,
+    jwt: String,
+    channel_id: String,
+}
+
+impl StreamElements {
+    /// Looks up the channel of the configured streamer, which also checks the JWT.
+    pub async fn connect(config: &Config) -> Result<StreamElements> {
+        StreamElements::connect_to(&api(), config).await
+    }
+
+    /// Like [`StreamElements::connect`], with the API at another base URL.
+    pub async fn connect_to(api: &str, config: &Config) -> Result<StreamElements> {
+        let client = reqwest::Client::new();
+        let channel_id = find_channel_id(get_accesses(&client, api, config).await?, config)?;
+        Ok(StreamElements::new(client, api, config, channel_id))
+    }
+
+    pub(crate) fn new(
+        client: reqwest::Client,
+        api: &str,
+        config: &Config,
+        channel_id: String,
+    ) -> StreamElements {
+        StreamElements {
+            client,
+            api: api.to_owned(),
+            jwt: config.jwt().to_owned(),
+            channel_id,
+        }
+    }
+
+    pub fn channel_id(&self) -> &str {
+        &self.channel_id
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}bot/commands/{}{}", self.api, self.channel_id, path)
+    }
+
+    pub async fn commands(&self) -> Result<Vec<CommandResponse>> {
+        self.client
+            .get(self.url(""))
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .header("Authorization", format!("Bearer {}", self.jwt))
+            .send_tracked("streamelements")
+            .await
+            .context("Failed sending request to get command list")?
+            .json::<Vec<CommandResponse>>()
+            .await
+            .context("Failed to parse response for command list request")
+    }
+
+    /// Gets a command by its ID if known, by name otherwise.
+    pub async fn command(&self, command: &CommandRef) -> Result<CommandResponse> {
+        if !command.command_id.is_empty() {
+            return self
+                .client
+                .get(self.url(&format!("/{}", command.command_id)))
+                .header("Content-Type", "application/json")
+                .header("Accept", "application/json")
+                .header("Authorization", format!("Bearer {}", self.jwt))
+                .send_tracked("streamelements")
+                .await
+                .context("Failed sending request to get command")?
+                .error_for_status()
+                .with_context(|| format!("command with id \"{}\" not found", command.command_id))?
+                .json::<CommandResponse>()
+                .await
+                .context("Failed to parse response for command request");
+        }
+
+        for found in self.commands().await? {
+            if found.command == command.command_name {
+                return Ok(found);
+            }
+        }
+
+        Err(anyhow!("command \"{}\" not found", command.command_name))
+    }
+
+    pub async fn update(&self, command: &CommandResponse) -> Result<CommandResponse> {
+        let command = self
+            .client
+            .put(self.url(&format!("/{}", command._id)))
+            .header("Content-Type", "application/json")
+            .header("Accept", "application/json")
+            .header("Authorization", format!("Bearer {}", self.jwt))
+            .json(command)
+            .send_tracked("streamelements")
+            .await
+            .context("Failed sending request to update command")?
+            .json::<CommandResponse>()
+            .await
+            .context("Failed to parse response for command update request")?;
+
+        if command.command.is_empty() {
+            return Err(anyhow!("command not updated correctly"));
+        }
+        Ok(command)
+    }
+
+    /// Turns a command on or off for when the stream is online.
+    pub async fn set_enabled(
+        &self,
+        command: &CommandRef,
+        enabled: bool,
+    ) -> Result<CommandResponse> {
+        let mut command = self.command(command).await?;
+        command.enabledOnline = enabled;
+        self.update(&command).await
+    }
+}
+
+fn bot_command(command: &CommandResponse) -> Result<BotCommand> {
+    Ok(BotCommand {
+        bot: Bot::StreamElements,
+        id: command._id.clone(),
+        name: command.command.clone(),
+        enabled: command.enabledOnline,
+        raw: serde_json::to_value(command)?,
+    })
+}
+
+#[async_trait]
+impl ChatBot for StreamElements {
+    fn bot(&self) -> Bot {
+        Bot::StreamElements
+    }
+
+    async fn find(&self, command: &CommandRef) -> Result<BotCommand> {
+        bot_command(&self.command(command).await?)
+    }
+
+    async fn enable(&self, command: &CommandRef) -> Result<BotCommand> {
+        bot_command(&self.set_enabled(command, true).await?)
+    }
+
+    async fn disable(&self, command: &CommandRef) -> Result<BotCommand> {
+        bot_command(&self.set_enabled(command, false).await?)
+    }
+
+    /// Puts back whether the command is enabled, its reply, cooldowns and cost.
+    async fn restore(&self, original: &BotCommand) -> Result<BotCommand> {
+        let enabled = original.enabled;
+        let mut command = self.restore_reply(original).await?;
+        command.enabledOnline = enabled;
+        bot_command(&self.update(&command).await?)
+    }
+}
+
+impl StreamElements {
+    /// Puts back the reply, cooldowns and cost of a snapshot, leaving
+    /// whether the command is enabled to the toggles.
+    async fn restore_reply(&self, original: &BotCommand) -> Result<CommandResponse> {
+        let original: CommandResponse = serde_json::from_value(original.raw.clone())
+            .context("Snapshot is not a StreamElements command")?;
+        let mut command = self
+            .command(&CommandRef {
+                command_name: original.command.clone(),
+                command_id: original._id.clone(),
+            })
+            .await?;
+        command.reply = original.reply;
+        command.cooldown = original.cooldown;
+        command.cost = original.cost;
+        Ok(command)
+    }
+}
+
+/// Rewrites a command's reply while the window is open.
+///
+/// The command as it was before is kept in [`snapshot`] until it has been
+/// restored, so a crash in between does not lose it.
+pub struct Rewrite {
+    reply: Reply,
+    original: Option<BotCommand>,
+}
+
+impl Rewrite {
+    pub fn new(reply: Reply) -> Rewrite {
+        Rewrite {
+            reply,
+            original: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Action for Rewrite {
+    fn name(&self) -> String {
+        format!("reply of !{}", self.reply.command.command_name)
+    }
+
+    async fn start(&mut self, context: &action::Context<'_>) -> Result<()> {
+        let profile = &context.config.profile;
+        let streamelements = context.bots.streamelements()?;
+        let command = streamelements.command(&self.reply.command).await?;
+        let original = snapshot::keep(profile, &bot_command(&command)?)?;
+
+        let mut changed = command;
+        changed.reply = context.render(&self.reply.template);
+        if let Some(user) = self.reply.user_cooldown_secs {
+            changed.cooldown.user = user;
+        }
+        if let Some(global) = self.reply.global_cooldown_secs {
+            changed.cooldown.global = global;
+        }
+        if let Some(cost) = self.reply.cost {
+            changed.cost = cost;
+        }
+        if let Err(err) = streamelements.update(&changed).await {
+            snapshot::forget(profile, &original)?;
+            return Err(err);
+        }
+        context.log(&format!(
+            "Changed reply of !{} to \"{}\"",
+            changed.command, changed.reply
+        ));
+        self.original = Some(original);
+        Ok(())
+    }
+
+    async fn stop(&mut self, context: &action::Context<'_>) -> Result<()> {
+        let Some(original) = self.original.take() else {
+            return Ok(());
+        };
+        let streamelements = context.bots.streamelements()?;
+        let command = streamelements
+            .update(&streamelements.restore_reply(&original).await?)
+            .await?;
+        snapshot::forget(&context.config.profile, &original)?;
+        context.log(&format!("Restored reply of !{}", command.command));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Mock;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn enables_a_command_found_by_name() {
+        let stored = Arc::new(Mutex::new(CommandResponse {
+            _id: "c1".to_owned(),
+            command: "fish".to_owned(),
+            reply: "Gone fishing".to_owned(),
+            ..Default::default()
+        }));
+        let command = Arc::clone(&stored);
+        let mock = Mock::start(move |request| {
+            let mut command = command.lock().unwrap();
+            match (request.method.as_str(), request.url.as_str()) {
+                ("GET", "/users/access") => (
+                    200,
+                    r#"[{"channelId":"ch1","username":"chobo","avatar":"","provider":"twitch","role":"owner"}]"#
+                        .to_owned(),
+                ),
+                ("GET", "/bot/commands/ch1") => (200, format!("[{}]", serde_json::to_string(&*command).unwrap())),
+                ("PUT", "/bot/commands/ch1/c1") => {
+                    *command = serde_json::from_str(&request.body).unwrap();
+                    (200, request.body.clone())
+                }
+                _ => (404, "{}".to_owned()),
+            }
+        });
+        let mut config = Config::empty();
+        config.jwt = "jwt".to_owned();
+
+        let streamelements = StreamElements::connect_to(&mock.url, &config)
+            .await
+            .unwrap();
+        assert_eq!(streamelements.channel_id(), "ch1");
+        let enabled = streamelements
+            .enable(&CommandRef::named("fish"))
+            .await
+            .unwrap();
+        assert!(enabled.enabled);
+        assert!(stored.lock().unwrap().enabledOnline);
+        assert_eq!(stored.lock().unwrap().reply, "Gone fishing");
+        assert!(mock
+            .requests()
+            .iter()
+            .all(|request| request.header("Authorization") == Some("Bearer jwt")));
+    }
+}
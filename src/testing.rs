@@ -0,0 +1,98 @@
+//! Helpers shared by the tests.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::profile;
+
+/// A temporary config directory of one test, removed when dropped.
+pub struct ConfigRoot(PathBuf);
+
+impl Drop for ConfigRoot {
+    fn drop(&mut self) {
+        profile::TEST_ROOT.with(|root| root.borrow_mut().take());
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Points the config directory of the calling thread at a new temporary
+/// one, so tests running in parallel never see each other's files. Work
+/// moved to other threads still sees the real one.
+pub fn config_root() -> ConfigRoot {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let root = std::env::temp_dir().join(format!(
+        "fishinge-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    profile::TEST_ROOT.with(|test_root| *test_root.borrow_mut() = Some(root.clone()));
+    ConfigRoot(root)
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Answers requests on localhost with the status and body `respond` returns
+/// for them, and keeps them for the test to look at.
+pub struct Mock {
+    /// With a trailing slash, like the API base URLs.
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Mock {
+    pub fn start(respond: impl Fn(&Request) -> (u16, String) + Send + 'static) -> Mock {
+        let server = tiny_http::Server::http("127.0.0.1:0").expect("mock server starts");
+        let port = server
+            .server_addr()
+            .to_ip()
+            .expect("mock server listens on TCP")
+            .port();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+        thread::spawn(move || {
+            for mut incoming in server.incoming_requests() {
+                let mut body = String::new();
+                let _ = incoming.as_reader().read_to_string(&mut body);
+                let request = Request {
+                    method: incoming.method().to_string(),
+                    url: incoming.url().to_owned(),
+                    headers: incoming
+                        .headers()
+                        .iter()
+                        .map(|header| (header.field.to_string(), header.value.to_string()))
+                        .collect(),
+                    body,
+                };
+                let (status, body) = respond(&request);
+                received.lock().expect("requests lock").push(request);
+                let _ = incoming
+                    .respond(tiny_http::Response::from_string(body).with_status_code(status));
+            }
+        });
+        Mock {
+            url: format!("http://127.0.0.1:{}/", port),
+            requests,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().expect("requests lock").clone()
+    }
+}
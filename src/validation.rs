@@ -0,0 +1,398 @@
+use crate::action::ActionConfig;
+use crate::announce::Via;
+use crate::chatbot::{Bot, ChatBot};
+use crate::nightbot::Nightbot;
+use crate::script;
+use crate::streamelements::{self, find_channel_id, get_accesses, CommandRef, StreamElements};
+use crate::{get_broadcaster_id, get_reward_id, validate_token, Config};
+use std::sync::{Arc, Mutex};
+
+/// Scopes of which at least one is needed to subscribe to reward redemptions.
+const REDEMPTION_SCOPES: [&str; 2] = ["channel:read:redemptions", "channel:manage:redemptions"];
+
+const TOKEN: usize = 0;
+const BROADCASTER: usize = 1;
+const BINDINGS: usize = 2;
+
+/// Step indices that depend on the bindings and the chat bots they use.
+struct Layout {
+    bindings: usize,
+    streamelements: bool,
+    nightbot: bool,
+}
+
+/// The chat bot commands used by any binding's actions, in order, with the
+/// bot and the action using them.
+fn commands(config: &Config) -> Vec<(Bot, &ActionConfig, &CommandRef)> {
+    config
+        .bindings
+        .iter()
+        .flat_map(|binding| binding.actions.iter().map(|action| (binding.bot, action)))
+        .filter_map(|(bot, action)| Some((action.bot(bot)?, action, action.command()?)))
+        .collect()
+}
+
+impl Layout {
+    fn new(config: &Config) -> Layout {
+        Layout {
+            bindings: config.bindings.len(),
+            streamelements: config.uses(Bot::StreamElements),
+            nightbot: config.uses(Bot::Nightbot),
+        }
+    }
+
+    fn reward(&self, binding: usize) -> usize {
+        BINDINGS + 1 + binding
+    }
+
+    /// The StreamElements steps, if any binding uses it.
+    fn streamelements(&self) -> Option<(usize, usize)> {
+        let jwt = BINDINGS + 1 + self.bindings;
+        self.streamelements.then_some((jwt, jwt + 1))
+    }
+
+    fn nightbot(&self) -> Option<usize> {
+        let index = BINDINGS + 1 + self.bindings + 2 * self.streamelements as usize;
+        self.nightbot.then_some(index)
+    }
+
+    fn command(&self, command: usize) -> usize {
+        BINDINGS
+            + 1
+            + self.bindings
+            + 2 * self.streamelements as usize
+            + self.nightbot as usize
+            + command
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Pending,
+    Running,
+    Passed(String),
+    Warning(String),
+    Failed(String),
+    Skipped(String),
+}
+
+impl Status {
+    /// Hard failures block launching, warnings do not.
+    pub fn is_hard_failure(&self) -> bool {
+        matches!(self, Status::Failed(_) | Status::Skipped(_))
+    }
+
+    pub fn is_done(&self) -> bool {
+        !matches!(self, Status::Pending | Status::Running)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub name: String,
+    pub status: Status,
+}
+
+pub fn steps(config: &Config) -> Vec<Step> {
+    let mut names = vec![
+        "Twitch client ID and token".to_owned(),
+        "Broadcaster".to_owned(),
+        "Bindings".to_owned(),
+    ];
+    for binding in &config.bindings {
+        names.push(format!("Reward \"{}\"", binding.reward_title()));
+    }
+    if config.uses(Bot::StreamElements) {
+        names.push("StreamElements JWT".to_owned());
+        names.push("StreamElements channel access".to_owned());
+    }
+    if config.uses(Bot::Nightbot) {
+        names.push("Nightbot token".to_owned());
+    }
+    for (bot, _, command) in commands(config) {
+        names.push(format!("{} command !{}", bot, command.command_name));
+    }
+    names
+        .into_iter()
+        .map(|name| Step {
+            name,
+            status: Status::Pending,
+        })
+        .collect()
+}
+
+/// The scope announcements need but the token lacks, if any.
+fn missing_chat_scope(config: &Config, scopes: &[String]) -> Option<&'static str> {
+    config
+        .bindings
+        .iter()
+        .flat_map(|binding| &binding.actions)
+        .filter_map(|action| match action {
+            ActionConfig::Announce(announcement) => Some(match announcement.via {
+                Via::Helix => "user:write:chat",
+                Via::Irc => "chat:edit",
+            }),
+            _ => None,
+        })
+        .find(|needed| !scopes.iter().any(|scope| scope == needed))
+}
+
+/// Whether every step has finished without a hard failure.
+pub fn passed(steps: &[Step]) -> bool {
+    steps
+        .iter()
+        .all(|step| step.status.is_done() && !step.status.is_hard_failure())
+}
+
+fn set(steps: &Arc<Mutex<Vec<Step>>>, index: usize, status: Status) {
+    if let Ok(mut steps) = steps.lock() {
+        steps[index].status = status;
+    }
+}
+
+/// Runs all checks in order, updating `steps` as each one starts and finishes,
+/// so the setup window can show progress while this runs on another task.
+pub async fn run(config: &Config, steps: &Arc<Mutex<Vec<Step>>>) {
+    let client = reqwest::Client::new();
+    let layout = Layout::new(config);
+
+    set(steps, TOKEN, Status::Running);
+    let token = validate_token(&client, config).await;
+    let missing_chat = token
+        .as_ref()
+        .ok()
+        .and_then(|info| missing_chat_scope(config, &info.scopes));
+    let status = match &token {
+        Err(err) => Status::Failed(format!("{:#}", err)),
+        Ok(info) if info.client_id != config.client_id() => Status::Failed(format!(
+            "token was issued for client ID {}, not {}",
+            info.client_id,
+            config.client_id()
+        )),
+        Ok(info)
+            if !info
+                .scopes
+                .iter()
+                .any(|scope| REDEMPTION_SCOPES.contains(&scope.as_str())) =>
+        {
+            Status::Failed("token is missing the channel:read:redemptions scope".to_owned())
+        }
+        Ok(info)
+            if config.refunds()
+                && !info
+                    .scopes
+                    .iter()
+                    .any(|scope| scope == "channel:manage:redemptions") =>
+        {
+            Status::Warning(
+                "refunds need the channel:manage:redemptions scope, rejected redemptions will not be refunded"
+                    .to_owned(),
+            )
+        }
+        Ok(_) if missing_chat.is_some() => Status::Warning(format!(
+            "announcements need the {} scope and will not be sent",
+            missing_chat.unwrap_or_default()
+        )),
+        Ok(info) if info.expires_in != 0 && info.expires_in < 24 * 60 * 60 => {
+            Status::Warning(format!("token expires in {} minutes", info.expires_in / 60))
+        }
+        Ok(info) => Status::Passed(format!("token belongs to {}", info.login)),
+    };
+    let token_ok = !status.is_hard_failure();
+    set(steps, TOKEN, status);
+
+    set(steps, BROADCASTER, Status::Running);
+    let broadcaster_id = match (&token, token_ok) {
+        (Ok(info), true) => match get_broadcaster_id(&client, config).await {
+            Ok(_) if info.login != config.streamer().to_lowercase() => {
+                set(
+                    steps,
+                    BROADCASTER,
+                    Status::Failed(format!(
+                        "token belongs to {}, but redemptions of {} can only be read with their own token",
+                        info.login,
+                        config.streamer()
+                    )),
+                );
+                None
+            }
+            Ok(id) => {
+                set(steps, BROADCASTER, Status::Passed(format!("id {}", id)));
+                Some(id)
+            }
+            Err(err) => {
+                set(steps, BROADCASTER, Status::Failed(format!("{:#}", err)));
+                None
+            }
+        },
+        _ => {
+            set(
+                steps,
+                BROADCASTER,
+                Status::Skipped("requires a valid Twitch token".to_owned()),
+            );
+            None
+        }
+    };
+
+    set(steps, BINDINGS, Status::Running);
+    let invalid_gate = config.bindings.iter().find_map(|binding| {
+        binding
+            .gate
+            .validate()
+            .err()
+            .map(|err| format!("schedule of \"{}\": {:#}", binding.reward_title(), err))
+    });
+    let invalid_action = config.bindings.iter().find_map(|binding| {
+        binding.actions.iter().find_map(|action| {
+            action.validate(config).err().map(|err| {
+                format!(
+                    "{} of \"{}\": {:#}",
+                    action.describe(),
+                    binding.reward_title(),
+                    err
+                )
+            })
+        })
+    });
+    let invalid_script = config.bindings.iter().find_map(|binding| {
+        if binding.script.is_empty() {
+            return None;
+        }
+        script::check(
+            &config.profile,
+            &binding.script,
+            config.uses(Bot::StreamElements),
+        )
+        .err()
+        .map(|err| format!("script of \"{}\": {:#}", binding.reward_title(), err))
+    });
+    let idle = config
+        .bindings
+        .iter()
+        .find(|binding| binding.actions.is_empty() && binding.script.is_empty());
+    let status = if config.bindings.is_empty() {
+        Status::Failed("no reward is bound to any actions".to_owned())
+    } else if let Some(err) = invalid_gate {
+        Status::Failed(err)
+    } else if let Some(err) = invalid_action {
+        Status::Failed(err)
+    } else if let Some(err) = invalid_script {
+        Status::Failed(err)
+    } else if let Some(binding) = idle {
+        Status::Failed(format!("\"{}\" has no actions", binding.reward_title()))
+    } else {
+        Status::Passed(format!("{} binding(s)", config.bindings.len()))
+    };
+    set(steps, BINDINGS, status);
+
+    for (index, binding) in config.bindings.iter().enumerate() {
+        let step = layout.reward(index);
+        set(steps, step, Status::Running);
+        let status = match &broadcaster_id {
+            Some(broadcaster_id) => {
+                match get_reward_id(&client, config, broadcaster_id, binding).await {
+                    Ok(id) => Status::Passed(format!("id {}", id)),
+                    Err(err) => Status::Failed(format!("{:#}", err)),
+                }
+            }
+            None => Status::Skipped("requires the broadcaster".to_owned()),
+        };
+        set(steps, step, status);
+    }
+
+    let streamelements = match layout.streamelements() {
+        Some((jwt, channel)) => check_streamelements(&client, config, steps, jwt, channel).await,
+        None => None,
+    };
+
+    let nightbot = match layout.nightbot() {
+        Some(step) => {
+            set(steps, step, Status::Running);
+            match Nightbot::connect(config).await {
+                Ok(nightbot) => {
+                    set(steps, step, Status::Passed("token accepted".to_owned()));
+                    Some(nightbot)
+                }
+                Err(err) => {
+                    set(steps, step, Status::Failed(format!("{:#}", err)));
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    for (index, (bot, action, command)) in commands(config).into_iter().enumerate() {
+        let step = layout.command(index);
+        set(steps, step, Status::Running);
+        let toggles = matches!(action, ActionConfig::ToggleCommand(_));
+        let client: Option<&dyn ChatBot> = match bot {
+            Bot::StreamElements => streamelements.as_ref().map(|client| client as _),
+            Bot::Nightbot => nightbot.as_ref().map(|client| client as _),
+        };
+        let status = match client {
+            Some(client) => match client.find(command).await {
+                Ok(command) if toggles && command.enabled => Status::Warning(format!(
+                    "!{} is currently enabled and will be disabled after the first redemption",
+                    command.name
+                )),
+                Ok(command) => Status::Passed(format!("found !{}", command.name)),
+                Err(err) => Status::Failed(format!("{:#}", err)),
+            },
+            None => Status::Skipped(format!("requires access to {}", bot)),
+        };
+        set(steps, step, status);
+    }
+}
+
+async fn check_streamelements(
+    client: &reqwest::Client,
+    config: &Config,
+    steps: &Arc<Mutex<Vec<Step>>>,
+    jwt: usize,
+    channel: usize,
+) -> Option<StreamElements> {
+    set(steps, jwt, Status::Running);
+    let api = streamelements::api();
+    let accesses = match get_accesses(client, &api, config).await {
+        Ok(accesses) => {
+            set(
+                steps,
+                jwt,
+                Status::Passed(format!("access to {} channel(s)", accesses.len())),
+            );
+            Some(accesses)
+        }
+        Err(err) => {
+            set(steps, jwt, Status::Failed(format!("{:#}", err)));
+            None
+        }
+    };
+
+    set(steps, channel, Status::Running);
+    match accesses {
+        Some(accesses) => match find_channel_id(accesses, config) {
+            Ok(id) => {
+                set(steps, channel, Status::Passed(format!("id {}", id)));
+                Some(StreamElements::new(client.clone(), &api, config, id))
+            }
+            Err(err) => {
+                set(
+                    steps,
+                    channel,
+                    Status::Failed(format!("{:#} for {}", err, config.streamer())),
+                );
+                None
+            }
+        },
+        None => {
+            set(
+                steps,
+                channel,
+                Status::Skipped("requires a valid JWT".to_owned()),
+            );
+            None
+        }
+    }
+}
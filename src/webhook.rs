@@ -0,0 +1,281 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::action::{self, render_with, Action};
+use crate::metrics::Tracked;
+use crate::{write_output, RedemptionEvent};
+
+/// Carries `sha256=<hex>`, the HMAC-SHA256 of the body, if a secret is set.
+pub const SIGNATURE_HEADER: &str = "X-Fishinge-Signature";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Doubled after each failed attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+fn default_method() -> String {
+    "POST".to_owned()
+}
+
+fn default_body() -> String {
+    r#"{"reward": "{reward}", "user": "{user}", "login": "{login}", "input": "{input}", "cost": {cost}}"#
+        .to_owned()
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+/// Sends the redemption that opened the window to a URL.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// JSON, see [`action::render`] for the placeholders. Filled in values
+    /// are escaped for JSON strings.
+    #[serde(default = "default_body")]
+    pub body: String,
+    /// Attempts after the first one, for network errors and 5xx or 429 responses.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Name of the webhook secret in the credentials file to sign the body
+    /// with, like all secrets kept out of the config file. Not signed if empty.
+    #[serde(default)]
+    pub secret: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Headers whose values are webhook secrets, like bearer tokens, by the
+    /// name of the secret.
+    #[serde(default)]
+    pub secret_headers: BTreeMap<String, String>,
+}
+
+/// The secrets a webhook sends with, looked up by name.
+#[derive(Default)]
+struct Secrets {
+    signing: Option<String>,
+    headers: BTreeMap<String, String>,
+}
+
+impl Default for Webhook {
+    fn default() -> Webhook {
+        Webhook {
+            url: String::new(),
+            method: default_method(),
+            body: default_body(),
+            retries: default_retries(),
+            secret: String::new(),
+            headers: BTreeMap::new(),
+            secret_headers: BTreeMap::new(),
+        }
+    }
+}
+
+/// The value of [`SIGNATURE_HEADER`].
+fn sign(secret: &str, body: &str) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|_| anyhow!("invalid HMAC secret"))?;
+    mac.update(body.as_bytes());
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+/// The value as it would appear between the quotes of a JSON string.
+fn json_escape(value: &str) -> String {
+    let quoted = serde_json::to_string(value).unwrap_or_default();
+    quoted[1..quoted.len() - 1].to_owned()
+}
+
+impl Webhook {
+    /// Checks what can be checked without sending anything, `secrets` being
+    /// the webhook secrets of the config.
+    pub fn validate(&self, secrets: &BTreeMap<String, String>) -> Result<()> {
+        reqwest::Url::parse(&self.url).context("invalid URL")?;
+        self.method()?;
+        self.secrets(secrets)?;
+        let example = RedemptionEvent {
+            user_input: "\"quoted\"\n".to_owned(),
+            ..Default::default()
+        };
+        let body = render_with(&self.body, &example, Duration::ZERO, json_escape);
+        serde_json::from_str::<serde_json::Value>(&body).context("body is not valid JSON")?;
+        Ok(())
+    }
+
+    fn method(&self) -> Result<Method> {
+        Method::from_bytes(self.method.to_uppercase().as_bytes())
+            .map_err(|_| anyhow!("invalid method \"{}\"", self.method))
+    }
+
+    fn secrets(&self, secrets: &BTreeMap<String, String>) -> Result<Secrets> {
+        let lookup = |name: &String| {
+            secrets
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("webhook secret \"{}\" is not set", name))
+        };
+        Ok(Secrets {
+            signing: match self.secret.is_empty() {
+                true => None,
+                false => Some(lookup(&self.secret)?),
+            },
+            headers: self
+                .secret_headers
+                .iter()
+                .map(|(header, name)| Ok((header.clone(), lookup(name)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Only the host, as webhook URLs often carry a token.
+    fn host(&self) -> String {
+        reqwest::Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_else(|| "an invalid URL".to_owned())
+    }
+
+    async fn send(&self, body: &str, secrets: &Secrets) -> Result<()> {
+        let method = self.method()?;
+        let signature = match &secrets.signing {
+            Some(secret) => Some(sign(secret, body)?),
+            None => None,
+        };
+        let client = Client::builder().timeout(TIMEOUT).build()?;
+
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 0;
+        loop {
+            let mut request = client
+                .request(method.clone(), &self.url)
+                .header("Content-Type", "application/json")
+                .body(body.to_owned());
+            for (name, value) in self.headers.iter().chain(&secrets.headers) {
+                request = request.header(name, value);
+            }
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+            // reqwest errors include the URL, which may carry a token.
+            let result = request
+                .send_tracked("webhook")
+                .await
+                .map_err(reqwest::Error::without_url);
+            let retry = match &result {
+                Ok(res) => res.status().is_server_error() || res.status().as_u16() == 429,
+                Err(_) => true,
+            };
+            if !retry || attempt == self.retries {
+                result
+                    .context("Failed sending webhook")?
+                    .error_for_status()
+                    .map_err(reqwest::Error::without_url)
+                    .context("Webhook was refused")?;
+                return Ok(());
+            }
+            attempt += 1;
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
+#[async_trait]
+impl Action for Webhook {
+    fn name(&self) -> String {
+        format!("webhook to {}", self.host())
+    }
+
+    /// Sends in the background, as retries can take a while, and only logs
+    /// failures. Missing secrets fail right away.
+    async fn start(&mut self, context: &action::Context<'_>) -> Result<()> {
+        let secrets = self.secrets(&context.config.webhook_secrets)?;
+        let body = render_with(&self.body, context.event, context.remaining, json_escape);
+        let webhook = self.clone();
+        let output = Arc::clone(context.output);
+        tokio::spawn(async move {
+            let text = match webhook.send(&body, &secrets).await {
+                Ok(()) => format!("Sent webhook to {}", webhook.host()),
+                Err(err) => format!("Could not send webhook to {}: {:#}", webhook.host(), err),
+            };
+            write_output(&output, &text);
+        });
+        Ok(())
+    }
+
+    async fn stop(&mut self, _context: &action::Context<'_>) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Mock;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn signs_the_body() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog").unwrap(),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_and_signs() {
+        let attempts = AtomicU32::new(0);
+        let mock = Mock::start(move |_| match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 => (503, String::new()),
+            _ => (204, String::new()),
+        });
+        let webhook = Webhook {
+            url: format!("{}hook?token=abc", mock.url),
+            retries: 1,
+            secret: "pond".to_owned(),
+            headers: BTreeMap::from([("X-Pond".to_owned(), "open".to_owned())]),
+            secret_headers: BTreeMap::from([("Authorization".to_owned(), "token".to_owned())]),
+            ..Default::default()
+        };
+        assert!(webhook.validate(&BTreeMap::new()).is_err());
+        let secrets = BTreeMap::from([
+            ("pond".to_owned(), "secret".to_owned()),
+            ("token".to_owned(), "Bearer abc".to_owned()),
+        ]);
+        webhook.validate(&secrets).unwrap();
+        let event = RedemptionEvent {
+            user_name: "viewer".to_owned(),
+            user_input: "\"hi\"".to_owned(),
+            ..Default::default()
+        };
+        let body = render_with(&webhook.body, &event, Duration::ZERO, json_escape);
+        webhook
+            .send(&body, &webhook.secrets(&secrets).unwrap())
+            .await
+            .unwrap();
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        let request = &requests[1];
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.url, "/hook?token=abc");
+        assert_eq!(request.header("X-Pond"), Some("open"));
+        assert_eq!(request.header("Authorization"), Some("Bearer abc"));
+        assert_eq!(
+            request.header(SIGNATURE_HEADER),
+            Some(sign("secret", &request.body).unwrap().as_str())
+        );
+        let sent: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(sent["user"], "viewer");
+        assert_eq!(sent["input"], "\"hi\"");
+    }
+}